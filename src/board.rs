@@ -1,4 +1,5 @@
 use crate::gen_table::print_board;
+use crate::zobrist;
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Piece {
     Empty,
@@ -12,6 +13,44 @@ pub enum Piece {
 
 use Piece::*;
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FenError {
+    MissingField,
+    Placement,
+    Player,
+    Castling,
+    EnPassant,
+    Fifty,
+    FullMoves,
+    KingCount,
+    PawnOnBackRank,
+    OpponentInCheck,
+    CastlingRights,
+    ExtraFields,
+}
+
+impl fmt::Display for FenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}",
+            match self {
+                FenError::MissingField    => "missing field",
+                FenError::Placement       => "malformed piece placement",
+                FenError::Player          => "malformed side to move",
+                FenError::Castling        => "malformed castling rights",
+                FenError::EnPassant       => "invalid en passant square",
+                FenError::Fifty           => "malformed halfmove clock",
+                FenError::FullMoves       => "malformed fullmove number",
+                FenError::KingCount       => "each side needs exactly one king",
+                FenError::PawnOnBackRank  => "pawn on the first or last rank",
+                FenError::OpponentInCheck => "side not to move is in check",
+                FenError::CastlingRights  => "castling rights without king and rook in place",
+                FenError::ExtraFields     => "unexpected fields after the fullmove number",
+            })
+    }
+}
+
+impl std::error::Error for FenError {}
+
 #[derive(Eq, Hash, Clone, Debug, PartialEq)]
 pub struct Board {
     pub pawn: u64,
@@ -24,6 +63,7 @@ pub struct Board {
     pub inverted: bool,
     pub castle_curr: [bool; 2],
     pub castle_other: [bool; 2],
+    pub key: u64,
 }
 
 impl Board {
@@ -39,6 +79,7 @@ impl Board {
             inverted: false,
             castle_curr: [false; 2],
             castle_other: [false; 2],
+            key: 0,
         }
     }
 
@@ -102,6 +143,98 @@ impl Board {
             }
         }
 
+        out.key = out.gen_key();
+        out
+    }
+
+    pub fn try_from_fen(fen: &str) -> Result<Board, FenError> {
+        let ranks: Vec<&str> = fen.split('/').collect();
+        let mut kings = [0, 0];
+
+        if ranks.len() != 8 {
+            return Err(FenError::Placement);
+        }
+
+        for (y, rank) in ranks.iter().enumerate() {
+            let mut x = 0;
+
+            for c in rank.chars() {
+                match c {
+                    '1'..='8' => x += c.to_digit(10).unwrap(),
+                    'p' | 'P' if y == 0 || y == 7 => return Err(FenError::PawnOnBackRank),
+                    'k' => {kings[1] += 1; x += 1},
+                    'K' => {kings[0] += 1; x += 1},
+                    'p' | 'n' | 'b' | 'r' | 'q' |
+                    'P' | 'N' | 'B' | 'R' | 'Q' => x += 1,
+                    _ => return Err(FenError::Placement),
+                }
+
+                if x > 8 {
+                    return Err(FenError::Placement);
+                }
+            }
+
+            if x != 8 {
+                return Err(FenError::Placement);
+            }
+        }
+
+        if kings != [1, 1] {
+            return Err(FenError::KingCount);
+        }
+
+        Ok(Board::from_fen(fen))
+    }
+
+    // piece placement field of a fen string, always from white's side
+    pub fn to_fen(&self) -> String {
+        let mut tmp;
+        let board =
+            if self.inverted {
+                tmp = self.clone();
+                tmp.invert();
+                &tmp
+            } else {
+                self
+            };
+
+        let mut out = String::new();
+
+        for y in (0..8).rev() {
+            let mut empty = 0;
+
+            for x in 0..8 {
+                let (piece, is_other) = board.get_loc(x + y * 8);
+
+                let c =
+                    match piece {
+                        Empty => {
+                            empty += 1;
+                            continue;
+                        },
+                        Pawn   => 'p',
+                        Knight => 'n',
+                        Bishop => 'b',
+                        Rook   => 'r',
+                        Queen  => 'q',
+                        King   => 'k',
+                    };
+
+                if empty > 0 {
+                    out.push_str(&empty.to_string());
+                    empty = 0;
+                }
+                out.push(if is_other {c} else {c.to_ascii_uppercase()});
+            }
+
+            if empty > 0 {
+                out.push_str(&empty.to_string());
+            }
+            if y > 0 {
+                out.push('/');
+            }
+        }
+
         out
     }
 
@@ -137,6 +270,7 @@ impl Board {
     } 
 
     pub fn invert(&mut self) {
+        self.key ^= zobrist::side_key() ^ zobrist::ep_key(self.pawn);
         self.pawn = self.pawn.swap_bytes();
         self.rook = self.rook.swap_bytes();
         self.bishop = self.bishop.swap_bytes();
@@ -148,6 +282,7 @@ impl Board {
         std::mem::swap(&mut self.curr, &mut self.other);
         std::mem::swap(&mut self.cking, &mut self.oking);
         std::mem::swap(&mut self.castle_curr, &mut self.castle_other);
+        self.key ^= zobrist::ep_key(self.pawn);
     }
 
     pub fn get_loc_piece(&self, loc: u8) -> Piece {
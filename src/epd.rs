@@ -0,0 +1,201 @@
+use crate::board::FenError;
+use crate::gen_table::Tables;
+use crate::movegen::Move;
+use crate::position::Position;
+use crate::san::{move_to_san, san_to_move};
+use crate::search::{MATE, Search, SearchLimits, SearchStats};
+
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum EpdError {
+    Fen(FenError),
+    Operation,
+    Move(String),
+}
+
+impl fmt::Display for EpdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EpdError::Fen(e)    => write!(f, "{}", e),
+            EpdError::Operation => write!(f, "malformed operation"),
+            EpdError::Move(m)   => write!(f, "invalid move {}", m),
+        }
+    }
+}
+
+impl std::error::Error for EpdError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Epd {
+    pub fen: String,
+    pub ops: Vec<(String, Vec<String>)>,
+}
+
+impl Epd {
+    // parses a line of four FEN fields followed by operations such as
+    // bm Qg6; id "WAC.001";
+    pub fn parse(line: &str) -> Result<Epd, EpdError> {
+        let line = line.trim();
+        let mut fields = Vec::new();
+        let mut rest = line;
+
+        for _ in 0..4 {
+            rest = rest.trim_start();
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            if end == 0 {
+                return Err(EpdError::Fen(FenError::MissingField));
+            }
+            fields.push(&rest[..end]);
+            rest = &rest[end..];
+        }
+
+        let mut ops = Vec::new();
+        let mut words = Vec::new();
+        let mut chars = rest.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                ';' => {
+                    if words.is_empty() {
+                        return Err(EpdError::Operation);
+                    }
+                    let name = words.remove(0);
+                    ops.push((name, std::mem::take(&mut words)));
+                },
+                '"' => {
+                    let s: String = std::iter::from_fn(|| chars.next_if(|c| *c != '"')).collect();
+                    if chars.next().is_none() || words.is_empty() {
+                        return Err(EpdError::Operation);
+                    }
+                    words.push(s);
+                },
+                c if c.is_whitespace() => {},
+                c => {
+                    let mut s = c.to_string();
+                    s.extend(std::iter::from_fn(|| chars.next_if(|c| !c.is_whitespace() && *c != ';')));
+                    words.push(s);
+                },
+            }
+        }
+
+        if !words.is_empty() {
+            return Err(EpdError::Operation);
+        }
+
+        Ok(Epd {
+            fen: fields.join(" "),
+            ops,
+        })
+    }
+
+    pub fn op(&self, name: &str) -> Option<&[String]> {
+        self.ops.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_slice())
+    }
+
+    fn op_number(&self, name: &str) -> Option<usize> {
+        self.op(name)?.first()?.parse().ok()
+    }
+
+    pub fn id(&self) -> Option<&str> {
+        self.op("id")?.first().map(|s| s.as_str())
+    }
+
+    pub fn acd(&self) -> Option<usize> {
+        self.op_number("acd")
+    }
+
+    pub fn dm(&self) -> Option<usize> {
+        self.op_number("dm")
+    }
+
+    pub fn position<'a>(&self, tables: &'a Tables) -> Result<Position<'a>, EpdError> {
+        let fen = format!("{} {} {}", self.fen,
+                          self.op_number("hmvc").unwrap_or(0),
+                          self.op_number("fmvn").unwrap_or(1));
+
+        Position::try_from_fen(tables, &fen).map_err(EpdError::Fen)
+    }
+
+    // the moves of a bm or am operation, which must all be legal in pos
+    pub fn moves(&self, pos: &Position, name: &str) -> Result<Vec<Move>, EpdError> {
+        self.op(name)
+            .unwrap_or(&[])
+            .iter()
+            .map(|s| san_to_move(pos, s).map_err(|_| EpdError::Move(s.clone())))
+            .collect()
+    }
+
+    // whether the engine's choice m with the given score solves this record
+    pub fn solved(&self, pos: &Position, m: &Move, score: i32) -> Result<bool, EpdError> {
+        let bm = self.moves(pos, "bm")?;
+        let am = self.moves(pos, "am")?;
+
+        Ok((self.op("bm").is_none() || bm.contains(m)) &&
+            !am.contains(m) &&
+            self.dm().map_or(true, |n| score >= MATE - 2 * n as i32))
+    }
+}
+
+// searches every record with the given limits, or to its acd depth if no
+// limit is given, and prints the outcome. Returns the number solved.
+pub fn run_suite(tables: &Tables, records: &[Epd], limits: &SearchLimits) -> usize {
+    let mut solved = 0;
+    let mut nodes = 0;
+    let mut stats = SearchStats::default();
+
+    for (i, record) in records.iter().enumerate() {
+        let id = record.id().map_or((i + 1).to_string(), |s| s.to_string());
+        let pos =
+            match record.position(tables) {
+                Ok(p) => p,
+                Err(e) => {
+                    println!("{}: invalid position: {}", id, e);
+                    continue;
+                }
+            };
+
+        if let Err(e) = record.moves(&pos, "bm").and(record.moves(&pos, "am")) {
+            println!("{}: {}", id, e);
+            continue;
+        }
+
+        let mut limits = limits.clone();
+        if limits.time.is_none() && limits.depth.is_none() {
+            limits.depth = record.acd();
+            if limits.depth.is_none() {
+                limits.time = Some(1000);
+            }
+        }
+
+        let mut search = Search::new(limits);
+        let (m, score) = search.run(pos.clone());
+        nodes += search.nodes;
+        stats.searches += search.stats.searches;
+        stats.fail_highs += search.stats.fail_highs;
+        stats.fail_lows += search.stats.fail_lows;
+
+        let ok = m.as_ref().map_or(false, |m| record.solved(&pos, m, score) == Ok(true));
+        if ok {
+            solved += 1;
+        }
+
+        let expected: Vec<String> =
+            ["bm", "am"].iter()
+                .filter_map(|op| record.op(op).map(|v| format!("{} {}", op, v.join(" "))))
+                .collect();
+
+        println!("{}: {} {} {} ({})",
+                 id,
+                 m.as_ref().map_or("none".to_string(), |m| move_to_san(&pos, m)),
+                 score,
+                 if ok {"solved"} else {"FAILED"},
+                 expected.join(", "));
+    }
+
+    println!();
+    println!("Solved: {}  Failed: {}  Total: {}", solved, records.len() - solved, records.len());
+    println!("Nodes: {}  Root searches: {}  Re-searches: {} ({} high, {} low)",
+             nodes, stats.searches, stats.researches(), stats.fail_highs, stats.fail_lows);
+    solved
+}
@@ -1,4 +1,5 @@
 use crate::position::*;
+use crate::board::{Piece, Piece::*};
 use crate::gen_table::{print_board, LocStack};
 
 const PAWN   : i32 = 100;
@@ -141,6 +142,17 @@ const OTHER_KING_TABLE:[i32; 64] = [
 
 const THREATS: i32 = 1;
 
+pub fn piece_value(piece: Piece) -> i32 {
+    match piece {
+        Pawn   => PAWN,
+        Knight => KNIGHT,
+        Bishop => BISHOP,
+        Rook   => ROOK,
+        Queen  => QUEEN,
+        _      => 0,
+    }
+}
+
 fn eval_tables(bits: u64, table: [i32; 64]) -> i32 {
     let mut out = 0;
 
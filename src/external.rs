@@ -0,0 +1,165 @@
+use crate::position::Position;
+use crate::search::SearchLimits;
+use crate::selfplay::{Clocks, Player};
+
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{Receiver, channel};
+use std::time::{Duration, Instant};
+
+// extra time an engine gets to answer before it is considered hung
+const GRACE: u64 = 5000;
+
+// how long a search limited by depth or nodes may take
+const UNTIMED: u128 = 600000;
+
+// an engine in another process speaking UCI
+pub struct External {
+    name: String,
+    id: Option<String>,
+    limits: SearchLimits,
+    child: Child,
+    stdin: ChildStdin,
+    lines: Receiver<String>,
+}
+
+impl External {
+    // parses a configuration such as "cmd=/usr/bin/stockfish,depth=8,option.Threads=1"
+    // and starts the engine
+    pub fn parse(s: &str) -> Option<External> {
+        let mut cmd = None;
+        let mut name = None;
+        let mut limits = SearchLimits::default();
+        let mut options = Vec::new();
+
+        for opt in s.split(',') {
+            let mut kv = opt.splitn(2, '=');
+            let (key, value) = (kv.next()?, kv.next()?);
+
+            match key {
+                "cmd"   => cmd = Some(value),
+                "name"  => name = Some(value.to_string()),
+                "time"  => limits.time = Some(value.parse().ok()?),
+                "depth" => limits.depth = Some(value.parse().ok()?),
+                "nodes" => limits.nodes = Some(value.parse().ok()?),
+                _ if key.starts_with("option.") => options.push((&key[7..], value)),
+                _ => return None,
+            }
+        }
+
+        let mut words = cmd?.split_whitespace();
+        let mut child = Command::new(words.next()?)
+            .args(words)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .ok()?;
+
+        let stdin = child.stdin.take()?;
+        let stdout = BufReader::new(child.stdout.take()?);
+        let (tx, lines) = channel();
+
+        std::thread::spawn(move || {
+            for line in stdout.lines() {
+                match line {
+                    Ok(l) => if tx.send(l).is_err() {break},
+                    Err(_) => break,
+                }
+            }
+        });
+
+        let mut out = External {
+            name: String::new(),
+            id: None,
+            limits,
+            child,
+            stdin,
+            lines,
+        };
+
+        out.send("uci");
+        out.wait_for("uciok", GRACE)?;
+        out.name = name.or(out.id.take()).unwrap_or_else(|| cmd.unwrap().to_string());
+
+        for (option, value) in options {
+            out.send(&format!("setoption name {} value {}", option, value));
+        }
+        out.send("isready");
+        out.wait_for("readyok", GRACE)?;
+
+        Some(out)
+    }
+
+    fn send(&mut self, s: &str) {
+        // a dead engine shows up as a missing reply
+        let _ = writeln!(self.stdin, "{}", s);
+    }
+
+    // reads lines until one starting with prefix and returns the rest of it,
+    // or None if the engine exits or takes more than timeout milliseconds
+    fn wait_for(&mut self, prefix: &str, timeout: u64) -> Option<String> {
+        let deadline = Instant::now() + Duration::from_millis(timeout);
+
+        loop {
+            let line = self.lines.recv_timeout(deadline.saturating_duration_since(Instant::now())).ok()?;
+
+            if let Some(rest) = line.strip_prefix("id name ") {
+                self.id = Some(rest.to_string());
+            }
+            if let Some(rest) = line.strip_prefix(prefix) {
+                return Some(rest.trim().to_string());
+            }
+        }
+    }
+}
+
+impl Player for External {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn new_game(&mut self) {
+        self.send("ucinewgame");
+        self.send("isready");
+        self.wait_for("readyok", GRACE);
+    }
+
+    fn go(&mut self, start: &str, moves: &[String], pos: &Position, clocks: Option<&Clocks>) -> Option<String> {
+        if moves.is_empty() {
+            self.send(&format!("position fen {}", start));
+        } else {
+            self.send(&format!("position fen {} moves {}", start, moves.join(" ")));
+        }
+
+        let (go, timeout) =
+            match (clocks, &self.limits) {
+                (Some(c), _) =>
+                    (format!("go wtime {} btime {} winc {} binc {}", c.remaining[0], c.remaining[1], c.inc, c.inc),
+                     c.remaining[pos.board.inverted as usize]),
+                (None, SearchLimits {time: Some(t), ..}) => (format!("go movetime {}", t), *t),
+                (None, SearchLimits {depth: Some(d), ..}) => (format!("go depth {}", d), UNTIMED),
+                (None, SearchLimits {nodes: Some(n), ..}) => (format!("go nodes {}", n), UNTIMED),
+                (None, _) => ("go movetime 100".to_string(), 100),
+            };
+
+        // no reply in time loses the game like an illegal one
+        self.send(&go);
+        let reply = self.wait_for("bestmove", timeout as u64 + GRACE)?;
+        reply.split_whitespace().next().map(|m| m.to_string())
+    }
+}
+
+impl Drop for External {
+    fn drop(&mut self) {
+        self.send("quit");
+
+        for _ in 0..50 {
+            if let Ok(Some(_)) = self.child.try_wait() {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
@@ -10,6 +10,19 @@ mod moves;
 mod position;
 mod search;
 mod eval;
+mod zobrist;
+mod tt;
+mod ordering;
+mod perft;
+mod san;
+mod pgn;
+mod epd;
+mod selfplay;
+mod external;
+mod book;
+mod syzygy;
+mod uci;
+mod xboard;
 
 use crate::gen_table::*;
 use crate::board::{Board, Piece, Piece::*};
@@ -20,11 +33,116 @@ use crate::search::*;
 use std::io::Write;
 
 fn main() {
+    let mut book = None;
+
+    match std::env::args().nth(1).as_deref() {
+        Some("uci") => return uci::run(),
+        Some("xboard") => return xboard::run(),
+        Some(mode @ "perft") | Some(mode @ "divide") => {
+            let args: Vec<String> = std::env::args().skip(2).collect();
+            let depth = args.get(0).and_then(|d| d.parse().ok()).unwrap_or(5);
+            let fen = if args.len() > 1 {args[1..].join(" ")} else {position::START_FEN.to_string()};
+            let tables = new_tables();
+            let pos = Position::from_fen(&tables, &fen);
+
+            if mode == "perft" {
+                println!("{}", perft::perft(&pos, depth));
+            } else {
+                perft::print_divide(&pos, depth);
+            }
+            return;
+        },
+        Some("mate") => {
+            let args: Vec<String> = std::env::args().skip(2).collect();
+            let n: usize =
+                match args.get(0).map(|n| n.parse()) {
+                    Some(Ok(n)) => n,
+                    _ => return eprintln!("usage: mate <moves> [fen]"),
+                };
+            let fen = if args.len() > 1 {args[1..].join(" ")} else {position::START_FEN.to_string()};
+            let tables = new_tables();
+            let pos =
+                match Position::try_from_fen(&tables, &fen) {
+                    Ok(p) => p,
+                    Err(e) => return eprintln!("invalid position: {}", e),
+                };
+
+            match (1..=n).find_map(|k| find_mate(&pos, k).map(|m| (k, m))) {
+                Some((k, m)) => println!("mate in {}: {}", k, san::move_to_san(&pos, &m)),
+                None => println!("no mate in {}", n),
+            }
+            return;
+        },
+        Some("pgn") => {
+            let tables = new_tables();
+            let path = std::env::args().nth(2).unwrap_or_default();
+            let text = std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {}", path, e));
+
+            match pgn::parse(&tables, &text) {
+                Ok(games) => {
+                    for (i, g) in games.iter().enumerate() {
+                        println!("{}", g);
+
+                        // a game over on the board must be scored accordingly
+                        let result = g.end(&tables).map_or("*", |mut p| pgn::result_string(p.test_endgame()));
+                        if result != "*" && result != g.result {
+                            eprintln!("{}: game {} ends in {} but is scored {}", path, i + 1, result, g.result);
+                        }
+                    }
+                },
+                Err(e) => eprintln!("{}: {}", path, e),
+            }
+            return;
+        },
+        Some("selfplay") => {
+            let args: Vec<String> = std::env::args().skip(2).collect();
+            return selfplay::run(&args);
+        },
+        Some("bench-epd") => {
+            let args: Vec<String> = std::env::args().skip(2).collect();
+            let path = args.get(0).cloned().unwrap_or_default();
+            let text = std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {}", path, e));
+            let mut limits = SearchLimits::default();
+
+            match (args.get(1).map(|s| s.as_str()), args.get(2).and_then(|n| n.parse().ok())) {
+                (Some("time"), Some(n)) => limits.time = Some(n as u128),
+                (Some("depth"), Some(n)) => limits.depth = Some(n),
+                _ => {}
+            }
+
+            let mut records = Vec::new();
+            for (i, line) in text.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+                match epd::Epd::parse(line) {
+                    Ok(r) => records.push(r),
+                    Err(e) => eprintln!("{}:{}: {}", path, i + 1, e),
+                }
+            }
+
+            epd::run_suite(&new_tables(), &records, &limits);
+            return;
+        },
+        Some("book") => {
+            let args: Vec<String> = std::env::args().skip(2).collect();
+
+            match book::Book::open(args.get(0).map_or("", |s| s)) {
+                Ok(mut b) => {
+                    b.depth = args.get(1).and_then(|d| d.parse().ok()).unwrap_or(b.depth);
+                    book = Some(b);
+                },
+                Err(e) => return eprintln!("usage: book <file> [depth]: {}", e),
+            }
+        },
+        _ => {}
+    }
+
     let tables = new_tables();
     // let mut position = Position::from_fen(&tables, "1kr4r/1bq1pp1p/pn3Pp1/1pp4n/4P2P/P1NNQP1B/1PP5/2KR3R w - - 0 1");
     let mut position = Position::from_fen(&tables, "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
 
     let mut tree = GameTree::new();
+    let mut game = pgn::Game::new(position::START_FEN);
+    game.set_tag("White", "Human");
+    game.set_tag("Black", "chess_engine");
 
     let mut pos = position.clone();
     // position.board.invert();
@@ -66,7 +184,7 @@ fn main() {
         let mut mov = Moves::string_to_move(&buf, &position.board);
         position.set_moves();
         let moves = position.gen_moves();
-        while !moves.contains(&mov) {
+        while !mov.as_ref().map_or(false, |m| moves.contains(m)) {
             println!("Invalid move!");
             print!("Enter your move: ");
             std::io::stdout().flush();
@@ -74,7 +192,8 @@ fn main() {
             stdin.read_line(&mut buf);
             mov = Moves::string_to_move(&buf, &position.board);
         }
-        position.do_move(&mov);
+        game.push(&position, mov.as_ref().unwrap());
+        position.do_move(&mov.unwrap());
         position.board.invert();
         println!("{}", position.board);
         position.set_moves();
@@ -84,17 +203,27 @@ fn main() {
         }
 
         /* ==================== Alpha-Beta AI ====================== */
-        let (mov, score) = ab_search(position.clone(), 5000);
-        let mov = mov.unwrap();
+        let (mov, score) =
+            match book.as_ref().and_then(|b| b.choose(&position)) {
+                Some(m) => (m, 0),
+                None => {
+                    let (m, score) = ab_search(position.clone(), 5000);
+                    (m.unwrap(), score)
+                }
+            };
 
         // tree.do_move(&mov);
         println!("{}", Moves::move_to_string(&mov, position.board.inverted));
         println!("{}", score);
+        game.push(&position, &mov);
         position.do_move(&mov);
         position.board.invert();
         println!("{}", position.board);
         position.set_moves();
     }
+
+    game.set_result(pgn::result_string(position.test_endgame()));
+    println!("{}", game);
 }
 
 mod tests {
@@ -126,6 +255,7 @@ mod tests {
         let mut board2 = Board::from_fen("2kr3r/1pp5/p1nnqp1b/4p2p/1PP4N/PN3pP1/1BQ1PP1P/1KR4R");
         board1.invert();
         board2.inverted = true;
+        board2.key = board2.gen_key();
         assert_eq!(board1, board2);
         board1.invert();
         board1.invert();
@@ -160,6 +290,7 @@ mod tests {
         // Test En_passant
         board = Board::from_fen("8/8/8/3Pp3/8/8/8/8");
         board.pawn |= 1 << 60;
+        board.key = board.gen_key();
         board.do_move(&En_passant(3, 4));
         assert_eq!(board, Board::from_fen("8/8/4P3/8/8/8/8/8"));
 
@@ -184,6 +315,41 @@ mod tests {
         assert_eq!(board, Board::from_fen("1Q6/8/8/8/8/8/8/8"));
     }
 
+    #[test]
+    fn test_capture_rook_castle() {
+        let tables = new_tables();
+
+        // Bxh8 and Rxa8, after which black is to move
+        for (fen, m, curr, other) in [
+            ("r3k2r/6B1/8/8/8/8/8/R3K2R w KQkq - 0 1", Basic(54, 63), [true, false], [true, true]),
+            ("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", Basic(0, 56), [false, true], [false, true]),
+        ] {
+            let mut pos = Position::from_fen(&tables, fen);
+            pos.do_move(&m);
+            pos.board.invert();
+            assert_eq!((pos.board.castle_curr, pos.board.castle_other), (curr, other));
+            assert_eq!(pos.board.key, pos.board.gen_key());
+        }
+    }
+
+    #[test]
+    fn test_pawn_moves() {
+        let tables = new_tables();
+
+        // e2e4 next to the f4 pawn allows fxe3
+        let mut pos = Position::from_fen(&tables, "4k3/8/8/8/5p2/8/4P3/4K3 w - - 0 1");
+        pos.do_move(&Basic(12, 28));
+        pos.board.invert();
+        pos.set_moves();
+        assert!(pos.gen_moves().contains(&En_passant(5, 4)));
+        assert_eq!(pos.board.key, pos.board.gen_key());
+
+        // the knight can take the pawn giving check
+        let mut pos = Position::from_fen(&tables, "4k3/8/8/8/8/3p4/1N2K3/8 w - - 0 1");
+        pos.set_moves();
+        assert!(pos.gen_moves().contains(&Basic(9, 19)));
+    }
+
     #[test]
     fn test_test_endgame() {
         let tables = new_tables();
@@ -191,13 +357,1230 @@ mod tests {
         assert_eq!(position.test_endgame(), Some(2));
     }
 
+    #[test]
+    fn test_repetition() {
+        let tables = new_tables();
+        let mut pos = Position::from_fen(&tables, position::START_FEN);
+
+        for i in 0..8 {
+            let m = ["g1f3", "g8f6", "f3g1", "f6g8"][i % 4];
+            pos.do_move(&Moves::string_to_move(m, &pos.board).unwrap());
+            pos.board.invert();
+            pos.set_moves();
+
+            match i {
+                3 => {
+                    assert_eq!(pos.repetitions(), 1);
+                    assert_eq!(pos.test_endgame(), None);
+                },
+                7 => {
+                    assert_eq!(pos.repetitions(), 2);
+                    assert_eq!(pos.test_endgame(), Some(1));
+                },
+                0..=2 => assert_eq!(pos.repetitions(), 0),
+                _ => assert_eq!(pos.repetitions(), 1),
+            }
+        }
+
+        // Nf3 Nf6, a null move, Ng8 and another null move reach the position
+        // after Nf3 again, which is no repetition
+        let mut pos = Position::from_fen(&tables, position::START_FEN);
+        for m in ["g1f3", "g8f6", "", "f6g8", ""].iter() {
+            if m.is_empty() {
+                pos.do_null_move();
+            } else {
+                pos.do_move(&Moves::string_to_move(m, &pos.board).unwrap());
+            }
+            pos.board.invert();
+        }
+        assert_eq!(pos.board.key, pos.history[1]);
+        assert_eq!(pos.repetitions(), 0);
+    }
+
+    #[test]
+    fn test_unmake_move() {
+        let tables = new_tables();
+
+        for _ in 0..100 {
+            let mut pos = Position::from_fen(&tables, "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+            pos.set_moves();
+
+            while pos.test_endgame() == None {
+                let moves = pos.gen_moves();
+
+                for m in moves.iter() {
+                    let board = pos.board.clone();
+                    let fifty = pos.fifty;
+                    let undo = pos.do_move(m);
+                    pos.board.invert();
+                    pos.board.invert();
+                    pos.unmake_move(m, undo);
+                    assert_eq!(pos.board, board);
+                    assert_eq!(pos.fifty, fifty);
+                }
+
+                pos.do_move(&moves[rand::random::<usize>() % moves.len()]);
+                pos.board.invert();
+                pos.set_moves();
+            }
+        }
+    }
+
+    #[test]
+    fn test_to_fen() {
+        let tables = new_tables();
+
+        for fen in [
+            position::START_FEN,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            "rnbqkbnr/pppp1ppp/8/8/3Pp3/5N2/PPP1PPPP/RNBQKB1R b Kq d3 0 3",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 b - - 12 40",
+        ].iter() {
+            assert_eq!(Position::from_fen(&tables, fen).to_fen(), *fen);
+        }
+
+        for _ in 0..100 {
+            let mut pos = Position::from_fen(&tables, position::START_FEN);
+            pos.set_moves();
+
+            while pos.test_endgame() == None {
+                let moves = pos.gen_moves();
+                pos.do_move(&moves[rand::random::<usize>() % moves.len()]);
+                pos.board.invert();
+                pos.set_moves();
+
+                let copy = Position::from_fen(&tables, &pos.to_fen());
+                assert_eq!(copy.board, pos.board);
+                assert_eq!(copy.fifty, pos.fifty);
+                assert_eq!(copy.full_moves, pos.full_moves);
+            }
+        }
+    }
+
+    #[test]
+    fn test_try_from_fen() {
+        use crate::board::FenError::*;
+        let tables = new_tables();
+
+        for (fen, err) in [
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP w KQkq - 0 1", Placement),
+            ("rnbqkbnr/pppppppp/9/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", Placement),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNRR w KQkq - 0 1", Placement),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNX w KQkq - 0 1", Placement),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR", MissingField),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR x KQkq - 0 1", Player),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkx - 0 1", Castling),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KK - 0 1", Castling),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq e3 0 1", EnPassant),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq e6 0 1", EnPassant),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - x 1", Fifty),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 0", FullMoves),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 x", ExtraFields),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQ1BNR w kq - 0 1", KingCount),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKKNR w kq - 0 1", KingCount),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNP w kq - 0 1", PawnOnBackRank),
+            ("4k3/8/8/8/8/8/4R3/4K3 w - - 0 1", OpponentInCheck),
+            ("4k3/8/8/8/8/8/8/4K2R w KQ - 0 1", CastlingRights),
+        ].iter() {
+            assert_eq!(Position::try_from_fen(&tables, fen).err(), Some(*err), "{}", fen);
+        }
+
+        assert!(Position::try_from_fen(&tables, position::START_FEN).is_ok());
+        assert!(Position::try_from_fen(&tables, "4k3/8/8/8/8/8/8/4K2R w K -").is_ok());
+        assert!(Position::try_from_fen(&tables, "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3").is_ok());
+    }
+
+    #[test]
+    fn test_zobrist_key() {
+        let tables = new_tables();
+
+        for _ in 0..200 {
+            let mut pos = Position::from_fen(&tables, position::START_FEN);
+            pos.set_moves();
+
+            while pos.test_endgame() == None {
+                let moves = pos.gen_moves();
+                let mov = &moves[rand::random::<usize>() % moves.len()];
+
+                pos.do_move(mov);
+                assert_eq!(pos.board.key, pos.board.gen_key());
+                pos.board.invert();
+                assert_eq!(pos.board.key, pos.board.gen_key());
+                pos.set_moves();
+            }
+        }
+    }
+
+    #[test]
+    fn test_tt_mate_score() {
+        let tt = tt::TTable::new(1);
+
+        tt.store(1, 4, tt::Bound::Exact, MATE - 5, None, 3);
+        let entry = tt.probe(1).unwrap();
+        assert_eq!(tt::score_from_tt(entry.score, 7), MATE - 9);
+
+        tt.store(2, 4, tt::Bound::Lower, 120, None, 3);
+        let entry = tt.probe(2).unwrap();
+        assert_eq!(tt::score_from_tt(entry.score, 7), 120);
+        assert_eq!(entry.bound, tt::Bound::Lower);
+        assert!(tt.probe(3).is_none());
+    }
+
+    #[test]
+    fn test_lazy_smp() {
+        let tt = tt::TTable::new(1);
+
+        for m in [Castle_queen, En_passant(3, 4), Promotion(Knight, 52, 61)].iter() {
+            tt.store(7, 3, tt::Bound::Upper, -50, Some(m.clone()), 0);
+            let entry = tt.probe(7).unwrap();
+            assert_eq!((entry.depth, entry.bound, entry.score, entry.mov), (3, tt::Bound::Upper, -50, Some(m.clone())));
+        }
+
+        // threads overwriting each other's slots never see a mixed entry
+        std::thread::scope(|s| {
+            for t in 0..4u64 {
+                let tt = &tt;
+                s.spawn(move || {
+                    for i in 0..200000u64 {
+                        let key = (i * 4 + t) * 0x9E3779B97F4A7C15 % 1000003;
+                        tt.store(key, (key % 50) as usize, tt::Bound::Exact, key as i32, Some(Basic((key % 64) as u8, 0)), 0);
+                        if let Some(e) = tt.probe(key ^ 1) {
+                            assert_eq!((e.score, e.depth as u64, e.mov), ((key ^ 1) as i32, (key ^ 1) % 50, Some(Basic(((key ^ 1) % 64) as u8, 0))));
+                        }
+                    }
+                });
+            }
+        });
+
+        let tables = new_tables();
+        let pos = Position::from_fen(&tables, "7k/8/6K1/8/8/8/Q7/8 w - - 0 1");
+
+        let mut search = Search::new(SearchLimits {depth: Some(4), ..SearchLimits::default()});
+        search.threads = 4;
+        let (mov, score) = search.run(pos.clone());
+
+        assert_eq!(mov, Some(Basic(8, 56)));
+        assert!(score >= MATE - 2);
+        assert!(search.stop.load(std::sync::atomic::Ordering::Relaxed));
+
+        let mut single = Search::new(SearchLimits {depth: Some(4), ..SearchLimits::default()});
+        assert_eq!(single.run(pos).0, mov);
+    }
+
+    #[test]
+    fn test_time_manager() {
+        let mut limits = SearchLimits::default();
+
+        limits.set_clock(60000, 0, None);
+        assert_eq!((limits.time, limits.deadline), (Some(2000), Some(6000)));
+        limits.set_clock(10000, 1000, Some(40));
+        assert_eq!((limits.time, limits.deadline), (Some(1250), Some(3750)));
+        limits.set_clock(1000, 0, Some(1));
+        assert_eq!((limits.time, limits.deadline), (Some(950), Some(950)));
+
+        let tables = new_tables();
+        let mut pos = Position::from_fen(&tables, "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+        pos.set_moves();
+
+        // only the deadline can stop this search, which aborts the iteration
+        // running at that time and keeps the move of the one before
+        let limits = SearchLimits {time: Some(1 << 40), deadline: Some(150), ..SearchLimits::default()};
+        let mut search = Search::new(limits);
+        let (mov, _) = search.run(pos.clone());
+        assert!(search.stop.load(std::sync::atomic::Ordering::Relaxed));
+        assert!(search.elapsed() >= 150 && search.elapsed() < 10000);
+        assert!(pos.gen_moves().contains(&mov.unwrap()));
+        assert!(search.depth >= 1);
+    }
+
+    #[test]
+    fn test_move_ordering() {
+        let tables = new_tables();
+        let fens = [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4",
+            "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        ];
+
+        let nodes = |order_moves: bool| -> usize {
+            fens.iter().map(|fen| {
+                let mut search = Search::new(SearchLimits {depth: Some(3), ..SearchLimits::default()});
+                search.options.order_moves = order_moves;
+                search.run(Position::from_fen(&tables, fen));
+                search.nodes
+            }).sum()
+        };
+
+        let (ordered, unordered) = (nodes(true), nodes(false));
+        assert!(ordered < unordered);
+
+        let board = Board::from_fen("4k3/8/8/3q4/4P3/2N5/8/4K2R w - - 0 1");
+        assert!(ordering::mvv_lva(&board, &Basic(28, 35)) > ordering::mvv_lva(&board, &Basic(18, 35)));
+    }
+
+    #[test]
+    fn test_search_options() {
+        let tables = new_tables();
+        let mut pos = Position::from_fen(&tables, "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3");
+        let before = pos.clone();
+
+        let undo = pos.do_null_move();
+        pos.board.invert();
+        assert_eq!(pos.board.key, pos.board.gen_key());
+        pos.set_moves();
+        assert!(!pos.gen_moves().contains(&En_passant(4, 5)));
+        pos.board.invert();
+        pos.unmake_null_move(undo);
+        assert_eq!(pos.to_fen(), before.to_fen());
+        assert_eq!(pos.board.key, before.board.key);
+
+        let search = |fen: &str, options: SearchOptions| {
+            let mut search = Search::new(SearchLimits {depth: Some(5), ..SearchLimits::default()});
+            search.options = options;
+            let (m, _) = search.run(Position::from_fen(&tables, fen));
+            (m, search.nodes)
+        };
+        let off = SearchOptions {pvs: false, null_move: false, lmr: false, ..SearchOptions::default()};
+
+        // white wins a rook with a knight fork
+        let fork = "r3k3/8/8/3N4/8/8/8/4K3 w - - 0 1";
+        let (m, all) = search(fork, SearchOptions::default());
+        assert_eq!(m, Some(Basic(35, 50)));
+        for options in [off.clone(),
+                        SearchOptions {pvs: false, ..SearchOptions::default()},
+                        SearchOptions {null_move: false, ..SearchOptions::default()},
+                        SearchOptions {lmr: false, ..SearchOptions::default()}].iter() {
+            assert_eq!(search(fork, options.clone()).0, m);
+        }
+        assert!(all < search(fork, off.clone()).1);
+
+        // no null moves with only pawns left
+        let pawns = "8/5k2/3p4/1p1P4/1P3K2/8/8/8 w - - 0 1";
+        assert_eq!(search(pawns, SearchOptions::default()).1,
+                   search(pawns, SearchOptions {null_move: false, ..SearchOptions::default()}).1);
+    }
+
+    #[test]
+    fn test_aspiration() {
+        let tables = new_tables();
+        let fens = ["r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4",
+                    "r3k3/8/8/3N4/8/8/8/4K3 w - - 0 1",
+                    "r1b1kb1r/3q1ppp/pBp1pn2/8/Np3P2/5B2/PPP3PP/R2Q1RK1 w kq - 0 1"];
+
+        let mut researches = 0;
+        for fen in fens.iter() {
+            let mut results = Vec::new();
+            for &aspiration in [true, false].iter() {
+                let mut search = Search::new(SearchLimits {depth: Some(6), ..SearchLimits::default()});
+                search.options.aspiration = aspiration;
+                let result = search.run(Position::from_fen(&tables, fen));
+
+                // one root search per iteration plus the re-searches
+                assert_eq!(search.stats.searches, 6 + search.stats.researches());
+                if !aspiration {
+                    assert_eq!(search.stats.researches(), 0);
+                }
+                researches += search.stats.researches();
+                results.push(result);
+            }
+
+            // the window only changes how the result is found
+            assert!(results[0].0.is_some());
+            assert_eq!(results[0], results[1]);
+        }
+        assert!(researches > 0);
+    }
+
+    #[test]
+    fn test_pv() {
+        use std::sync::{Arc, Mutex};
+
+        let tables = new_tables();
+        let pos = Position::from_fen(&tables, "r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4");
+        let infos = Arc::new(Mutex::new(Vec::new()));
+
+        let mut search = Search::new(SearchLimits {depth: Some(5), ..SearchLimits::default()});
+        let log = infos.clone();
+        search.on_info = Some(Box::new(move |info| log.lock().unwrap().push(info.clone())));
+        let (mov, score) = search.run(pos.clone());
+
+        let infos = infos.lock().unwrap();
+        assert_eq!(infos.iter().map(|i| i.depth).collect::<Vec<_>>(), vec![1, 2, 3, 4, 5]);
+
+        let last = infos.last().unwrap();
+        assert_eq!(last.score, score);
+        assert!(last.seldepth >= 5);
+        assert_eq!(search.pv.first(), mov.as_ref());
+        assert_eq!(last.pv.len(), search.pv.len());
+
+        // the line is legal from the root
+        let mut p = pos.clone();
+        for s in last.pv.iter() {
+            let m = uci::parse_move(&p, s).unwrap();
+            p.do_move(&m);
+            p.board.invert();
+        }
+
+        let info = Info {depth: 3, seldepth: 5, multipv: 1, score: MATE - 3, nodes: 1000, time: 500, pv: vec!["a2a8".to_string()]};
+        assert_eq!(info.to_string(), "depth 3 seldepth 5 multipv 1 score mate 2 nodes 1000 nps 2000 time 500 pv a2a8");
+        assert_eq!(Info {score: -42, ..info.clone()}.to_string(), "depth 3 seldepth 5 multipv 1 score cp -42 nodes 1000 nps 2000 time 500 pv a2a8");
+        assert_eq!(mate_in(-MATE + 4), Some(-2));
+    }
+
+    #[test]
+    fn test_multipv() {
+        use std::sync::{Arc, Mutex};
+
+        let tables = new_tables();
+        let mut pos = Position::from_fen(&tables, "r3k3/8/8/3N4/8/8/8/4K3 w - - 0 1");
+        pos.set_moves();
+
+        let mut search = Search::new(SearchLimits {depth: Some(4), ..SearchLimits::default()});
+        let lines = search.top_moves(pos.clone(), 3);
+
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].0, Basic(35, 50));
+        assert!(lines.windows(2).all(|w| w[0].1 >= w[1].1));
+        assert_eq!(search.score, lines[0].1);
+        for (m, _, pv) in lines.iter() {
+            assert!(pos.gen_moves().contains(m));
+            assert_eq!(pv.first(), Some(m));
+        }
+        assert_ne!(lines[1].0, lines[2].0);
+
+        let mut single = Search::new(SearchLimits {depth: Some(4), ..SearchLimits::default()});
+        assert_eq!(single.run(pos.clone()), (Some(lines[0].0.clone()), lines[0].1));
+        assert_eq!(single.lines.len(), 1);
+
+        // fewer legal moves than lines
+        let mut search = Search::new(SearchLimits {depth: Some(2), ..SearchLimits::default()});
+        assert_eq!(search.top_moves(Position::from_fen(&tables, "k7/8/1K6/8/8/8/8/7R b - - 0 1"), 5).len(), 1);
+
+        // the best move changes from depth 2 to 3, and no iteration gives a
+        // move twice
+        let infos = Arc::new(Mutex::new(Vec::new()));
+        let log = infos.clone();
+        let mut search = Search::new(SearchLimits {depth: Some(4), ..SearchLimits::default()});
+        search.multipv = 3;
+        search.on_info = Some(Box::new(move |info| log.lock().unwrap().push((info.depth, info.pv[0].clone()))));
+        search.run(Position::from_fen(&tables, "8/8/8/3k4/8/8/3PK3/8 w - - 0 1"));
+
+        let infos = infos.lock().unwrap();
+        let lines = |depth| infos.iter().filter(|i| i.0 == depth).map(|i| i.1.clone()).collect::<Vec<_>>();
+        assert_ne!(lines(2)[0], lines(3)[0]);
+        for depth in 1..=4 {
+            let mut moves = lines(depth);
+            assert_eq!(moves.len(), 3);
+            moves.sort();
+            moves.dedup();
+            assert_eq!(moves.len(), 3);
+        }
+    }
+
+    #[test]
+    fn test_mate_scores() {
+        let tables = new_tables();
+        let pos = Position::from_fen(&tables, "1r4k1/5ppp/8/8/8/8/4RPPP/4R1K1 w - - 0 1");
+
+        let mut search = Search::new(SearchLimits {depth: Some(4), ..SearchLimits::default()});
+        assert_eq!(search.run(pos.clone()), (Some(Basic(12, 60)), MATE - 3));
+        assert_eq!(mate_in(search.score), Some(2));
+
+        // the defender sees how soon it is mated
+        let mut pos2 = pos.clone();
+        pos2.do_move(&Basic(12, 60));
+        pos2.board.invert();
+        let mut search = Search::new(SearchLimits {depth: Some(4), ..SearchLimits::default()});
+        assert_eq!(search.run(pos2).1, -MATE + 2);
+
+        // a mate in one is preferred to the slower one
+        let faster = Position::from_fen(&tables, "6k1/5ppp/8/8/8/8/4RPPP/4R1K1 w - - 0 1");
+        let mut search = Search::new(SearchLimits {depth: Some(4), ..SearchLimits::default()});
+        assert_eq!(search.run(faster.clone()).1, MATE - 1);
+
+        assert_eq!(find_mate(&pos, 1), None);
+        assert_eq!(find_mate(&pos, 2), Some(Basic(12, 60)));
+        assert_eq!(find_mate(&faster, 1), Some(Basic(12, 60)));
+        assert_eq!(find_mate(&Position::from_fen(&tables, position::START_FEN), 2), None);
+    }
+
+    #[cfg(test)]
+    fn test_perft_position(fen: &str, counts: &[usize]) {
+        let tables = new_tables();
+        let pos = Position::from_fen(&tables, fen);
+
+        for (depth, n) in counts.iter().enumerate() {
+            assert_eq!(perft::perft(&pos, depth + 1), *n, "depth {}", depth + 1);
+        }
+    }
+
+    #[test]
+    fn test_perft_start() {
+        test_perft_position(position::START_FEN, &[20, 400, 8902, 197281]);
+    }
+
+    #[test]
+    fn test_perft_kiwipete() {
+        test_perft_position(
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            &[48, 2039, 97862, 4085603]
+        );
+    }
+
+    #[test]
+    fn test_perft_position_3() {
+        test_perft_position("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", &[14, 191, 2812, 43238]);
+    }
+
+    #[test]
+    fn test_perft_position_4() {
+        test_perft_position(
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            &[6, 264, 9467]
+        );
+        test_perft_position(
+            "r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1",
+            &[6, 264, 9467]
+        );
+    }
+
+    #[test]
+    fn test_perft_position_5() {
+        test_perft_position(
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            &[44, 1486, 62379]
+        );
+    }
+
+    #[test]
+    fn test_perft_position_6() {
+        test_perft_position(
+            "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+            &[46, 2079, 89890]
+        );
+    }
+
+    #[test]
+    fn test_quiesce() {
+        let tables = new_tables();
+        let pos = Position::from_fen(&tables, "4k3/8/4p3/3p4/8/8/8/3QK3 w - - 0 1");
+        let mut search = Search::new(SearchLimits::default());
+
+        let (mov, _) = search.best_move(pos, 1, None);
+        assert_ne!(mov, Some(Basic(3, 35)));
+
+        // evasions are only searched in the first quiescence plies
+        let mut pos = Position::from_fen(&tables, "4k3/8/8/8/8/8/8/4RK2 b - - 0 1");
+        pos.set_moves();
+        search.nodes = 0;
+        assert_eq!(search.quiesce(&mut pos, -MATE, MATE, 0, QUIESCE_EVASIONS), eval::eval(&mut pos.clone()));
+        assert_eq!(search.nodes, 1);
+        search.quiesce(&mut pos, -MATE, MATE, 0, 0);
+        assert!(search.nodes > 2);
+    }
+
+    #[test]
+    fn test_tree_expand() {
+        let tables = new_tables();
+        let mut pos = Position::from_fen(&tables, "6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1");
+        let mut tree = GameTree::new();
+        tree.search(pos.clone());
+
+        pos.set_moves();
+        assert_eq!(tree.children.len(), pos.gen_moves().len());
+        for c in tree.children.iter() {
+            let child = c.borrow();
+            assert_eq!(child.endgame, child.mov == Basic(0, 56));
+            if child.endgame {
+                assert_eq!(child.score, 1.);
+            }
+        }
+    }
+
     #[test]
     fn test_string_to_move() {
         let mut board = Board::from_fen("Kqk5/8/8/8/8/8/8/8 w - - 0 1");
         let _ = Moves::string_to_move("c7c8", &board);
+        assert_eq!(Moves::string_to_move("c7", &board), None);
+        assert_eq!(Moves::string_to_move("z7c8", &board), None);
         ()
     }
 
+    #[test]
+    fn test_string_to_move_black() {
+        let tables = new_tables();
+        let pos = Position::from_fen(&tables, "r3k3/1P6/8/8/3pP3/8/6p1/4K3 b q e3 0 1");
+
+        assert_eq!(Moves::string_to_move("e8c8", &pos.board), Some(Castle_queen));
+        assert_eq!(Moves::string_to_move("d4e3", &pos.board), Some(En_passant(3, 4)));
+        assert_eq!(Moves::string_to_move("g2g1n", &pos.board), Some(Promotion(Knight, 54, 62)));
+        assert_eq!(Moves::string_to_move("g2g1", &pos.board), None);
+    }
+
+    #[test]
+    fn test_move_to_san() {
+        let tables = new_tables();
+
+        for (fen, mov, san) in [
+            (position::START_FEN, "g1f3", "Nf3"),
+            (position::START_FEN, "e2e4", "e4"),
+            ("4k3/8/8/8/8/5N2/8/1N2K3 w - - 0 1", "b1d2", "Nbd2"),
+            ("4k3/8/8/R7/8/8/8/R3K3 w - - 0 1", "a1a3", "R1a3"),
+            ("k7/8/8/8/8/2Q1Q3/8/2Q1K3 w - - 0 1", "c3d2", "Qc3d2"),
+            ("rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3", "e5f6", "exf6"),
+            ("r3k3/8/8/8/8/8/8/R3K3 w Qq - 0 1", "e1c1", "O-O-O"),
+            ("r3k3/8/8/8/8/8/8/3K4 b q - 0 1", "e8c8", "O-O-O+"),
+            ("7k/4P3/8/8/8/8/8/4K3 w - - 0 1", "e7e8q", "e8=Q+"),
+            ("r1bqkb1r/pppp1ppp/2n2n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 4 4", "h5f7", "Qxf7#"),
+        ].iter() {
+            let pos = Position::from_fen(&tables, fen);
+            let mov = Moves::string_to_move(mov, &pos.board).unwrap();
+
+            assert_eq!(san::move_to_san(&pos, &mov), *san);
+            assert_eq!(san::san_to_move(&pos, san), Ok(mov));
+        }
+    }
+
+    #[test]
+    fn test_san_to_move() {
+        use crate::san::SanError::*;
+        let tables = new_tables();
+        let pos = Position::from_fen(&tables, "4k3/8/8/8/8/5N2/8/1N2K3 w - - 0 1");
+
+        assert_eq!(san::san_to_move(&pos, "Nd2"), Err(Ambiguous));
+        assert_eq!(san::san_to_move(&pos, "Nd3"), Err(Illegal));
+        assert_eq!(san::san_to_move(&pos, "Nfd2"), Ok(Basic(21, 11)));
+        assert_eq!(san::san_to_move(&pos, "Ng1"), Ok(Basic(21, 6)));
+        assert_eq!(san::san_to_move(&pos, "N"), Err(Invalid));
+        assert_eq!(san::san_to_move(&pos, "Nz9"), Err(Invalid));
+
+        for _ in 0..20 {
+            let mut pos = Position::from_fen(&tables, position::START_FEN);
+            pos.set_moves();
+
+            while pos.test_endgame() == None {
+                let moves = pos.gen_moves();
+                for m in moves.iter() {
+                    assert_eq!(san::san_to_move(&pos, &san::move_to_san(&pos, m)).as_ref(), Ok(m));
+                }
+
+                pos.do_move(&moves[rand::random::<usize>() % moves.len()]);
+                pos.board.invert();
+                pos.set_moves();
+            }
+        }
+    }
+
+    #[test]
+    fn test_pgn_parse() {
+        let tables = new_tables();
+        let text = "[Event \"Test \\\"quoted\\\"\"]\n\
+                    [Result \"1-0\"]\n\
+                    \n\
+                    % escaped line\n\
+                    {Opening} 1. e4 $1 e5 (1... c5 {Sicilian} 2. Nf3 (2. c3) d6) 2. Nf3 Nc6\n\
+                    3.Bb5 a6?! ; Morphy\n\
+                    4. Ba4 1-0\n\
+                    \n\
+                    [FEN \"4k3/8/8/8/8/8/8/4K2R w K - 0 30\"]\n\
+                    30. O-O+ *";
+
+        let games = pgn::parse(&tables, text).unwrap();
+        assert_eq!(games.len(), 2);
+
+        let game = &games[0];
+        assert_eq!(game.tag("Event"), Some("Test \"quoted\""));
+        assert_eq!(game.result, "1-0");
+        assert_eq!(game.line.comment.as_deref(), Some("Opening"));
+        assert_eq!(game.line.moves.len(), 7);
+        assert_eq!(game.line.moves[0].nags, vec![1]);
+        assert_eq!(game.line.moves[5].nags, vec![6]);
+        assert_eq!(game.line.moves[5].comment.as_deref(), Some("Morphy"));
+
+        let sicilian = &game.line.moves[1].variations[0];
+        assert_eq!(sicilian.moves[0].san, "c5");
+        assert_eq!(sicilian.moves[0].comment.as_deref(), Some("Sicilian"));
+        assert_eq!(sicilian.moves[1].variations[0].moves[0].san, "c3");
+        assert_eq!(sicilian.moves[2].san, "d6");
+
+        assert_eq!(game.end(&tables).unwrap().to_fen(),
+                   "r1bqkbnr/1ppp1ppp/p1n5/4p3/B3P3/5N2/PPPP1PPP/RNBQK2R b KQkq - 1 4");
+        assert_eq!(games[1].line.moves[0].mov, Castle_king);
+        assert_eq!(games[1].result, "*");
+
+        assert!(pgn::parse(&tables, "1. e4 e4").is_err());
+        assert!(pgn::parse(&tables, "1. e4 (1. d4").is_err());
+        assert!(pgn::parse(&tables, "1. e4 {unterminated").is_err());
+        assert!(pgn::parse(&tables, "[Event ?]").is_err());
+    }
+
+    #[test]
+    fn test_pgn_write() {
+        let tables = new_tables();
+        let mut pos = Position::from_fen(&tables, "4k3/8/8/8/8/8/8/4K2R b K - 0 30");
+        let mut game = pgn::Game::new(&pos.to_fen());
+        pos.set_moves();
+
+        for s in ["Kd7", "O-O", "Kc6", "Rf6+"].iter() {
+            let m = san::san_to_move(&pos, s).unwrap();
+            game.push(&pos, &m);
+            pos.do_move(&m);
+            pos.board.invert();
+            pos.set_moves();
+        }
+        game.line.moves[1].comment = Some("castles".to_string());
+        game.set_result(pgn::result_string(pos.test_endgame()));
+
+        let text = game.to_string();
+        assert!(text.contains("[FEN \"4k3/8/8/8/8/8/8/4K2R b K - 0 30\"]"));
+        assert!(text.ends_with("\n30... Kd7 31. O-O {castles} 31... Kc6 32. Rf6+ *\n"));
+        assert_eq!(pgn::parse(&tables, &text).unwrap(), vec![game]);
+    }
+
+    #[test]
+    fn test_epd() {
+        let tables = new_tables();
+        let record = epd::Epd::parse("6k1/5ppp/8/8/8/8/8/R5K1 w - - bm Ra8# Re1; am Kf1; id \"mate; in one\"; dm 1; acd 3;").unwrap();
+
+        assert_eq!(record.fen, "6k1/5ppp/8/8/8/8/8/R5K1 w - -");
+        assert_eq!(record.id(), Some("mate; in one"));
+        assert_eq!(record.op("bm").unwrap(), ["Ra8#", "Re1"]);
+        assert_eq!(record.acd(), Some(3));
+        assert_eq!(record.dm(), Some(1));
+
+        let pos = record.position(&tables).unwrap();
+        assert_eq!(record.moves(&pos, "bm"), Ok(vec![Basic(0, 56), Basic(0, 4)]));
+        assert_eq!(record.solved(&pos, &Basic(0, 56), 0), Ok(false));
+        assert_eq!(record.solved(&pos, &Basic(6, 5), MATE), Ok(false));
+        assert_eq!(record.solved(&pos, &Basic(0, 56), MATE), Ok(true));
+
+        // a best move which is not legal solves nothing
+        let illegal = epd::Epd::parse("4k3/8/8/8/8/8/8/4K2R w - - bm Nf3;").unwrap();
+        let illegal_pos = illegal.position(&tables).unwrap();
+        assert_eq!(illegal.moves(&illegal_pos, "bm"), Err(epd::EpdError::Move("Nf3".to_string())));
+        assert!(illegal.solved(&illegal_pos, &Basic(7, 63), 0).is_err());
+
+        let limits = SearchLimits {depth: Some(2), ..SearchLimits::default()};
+        assert_eq!(epd::run_suite(&tables, &[record, illegal], &limits), 1);
+
+        assert!(epd::Epd::parse("8/8/8 w").is_err());
+        assert!(epd::Epd::parse("4k3/8/8/8/8/8/8/4K3 w - - bm").is_err());
+        assert!(epd::Epd::parse("4k3/8/8/8/8/8/8/4K3 w - - id \"x;").is_err());
+    }
+
+    #[test]
+    fn test_selfplay_stats() {
+        use crate::selfplay::{Sprt, Stats};
+
+        let even = Stats {wins: 30, draws: 40, losses: 30};
+        let (elo, margin) = even.elo().unwrap();
+        assert!(elo.abs() < 1e-9);
+        assert!(margin > 40.0 && margin < 60.0);
+
+        let strong = Stats {wins: 500, draws: 0, losses: 100};
+        assert!((strong.elo().unwrap().0 - 279.5).abs() < 0.1);
+
+        // no finite Elo when one side won every game
+        assert_eq!(Stats {wins: 3, draws: 0, losses: 0}.elo(), None);
+        assert_eq!(Stats {wins: 0, draws: 0, losses: 3}.elo(), None);
+        assert_eq!(Stats::default().elo(), None);
+        let (elo, margin) = Stats {wins: 9, draws: 0, losses: 1}.elo().unwrap();
+        assert!(elo.is_finite() && margin.is_finite());
+        assert_eq!(strong.sprt(0.0, 5.0, 0.05, 0.05), Sprt::H1);
+
+        let weak = Stats {wins: 100, draws: 0, losses: 300};
+        assert_eq!(weak.sprt(0.0, 5.0, 0.05, 0.05), Sprt::H0);
+        assert_eq!(even.sprt(0.0, 5.0, 0.05, 0.05), Sprt::Continue);
+
+        // bad arguments are reported instead of starting a match
+        let args = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        for bad in [&["tc", "x"][..], &["openings", "/nonexistent"], &["pgn", "/nonexistent/x.pgn"], &["sprt", "0"]] {
+            selfplay::run(&args(&[&["depth=1", "depth=1"][..], bad].concat()));
+        }
+
+        let mut stats = Stats::default();
+        stats.add("1-0", true);
+        stats.add("1-0", false);
+        stats.add("1/2-1/2", true);
+        assert_eq!(stats, Stats {wins: 1, draws: 1, losses: 1});
+    }
+
+    #[test]
+    fn test_selfplay_game() {
+        use crate::selfplay::{Engine, play_game};
+        let tables = new_tables();
+
+        let mut a = Engine::parse("name=a,depth=2,hash=1").unwrap();
+        let mut b = Engine::parse("name=b,depth=1,hash=1").unwrap();
+        assert!(Engine::parse("depth=x").is_none());
+
+        let game = play_game(&tables, "4k3/8/8/8/8/8/8/R3K3 w - - 0 1", &mut a, &mut b, None);
+        assert_eq!(game.tag("White"), Some("a"));
+        assert_ne!(game.result, "*");
+        assert_eq!(game.result, pgn::result_string(game.end(&tables).unwrap().test_endgame()));
+
+        struct Illegal;
+        impl selfplay::Player for Illegal {
+            fn name(&self) -> &str {"illegal"}
+            fn new_game(&mut self) {}
+            fn go(&mut self, _: &str, _: &[String], _: &Position, _: Option<&selfplay::Clocks>) -> Option<String> {
+                Some("e2e5".to_string())
+            }
+        }
+
+        let clocks = selfplay::Clocks::parse("1000+10");
+        assert_eq!(clocks, Some(selfplay::Clocks {remaining: [1000, 1000], inc: 10}));
+
+        let game = play_game(&tables, position::START_FEN, &mut a, &mut Illegal, clocks);
+        assert_eq!(game.line.moves.len(), 1);
+        assert_eq!(game.tag("Termination"), Some("rules infraction"));
+        assert_eq!(game.result, "1-0");
+    }
+
+    #[test]
+    fn test_external_engine() {
+        use crate::selfplay::{Engine, play_game};
+        use crate::external::External;
+        let tables = new_tables();
+        let dir = std::env::temp_dir().join(format!("external_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // a fake UCI engine answering every go with the given line
+        let fake = |name: &str, go: &str| {
+            let path = dir.join(name);
+            std::fs::write(&path, format!(
+                "while read cmd rest; do\n\
+                 case $cmd in\n\
+                 uci) echo 'id name {name}'; echo uciok;;\n\
+                 isready) echo readyok;;\n\
+                 go) {go};;\n\
+                 quit) exit;;\n\
+                 esac\n\
+                 done\n")).unwrap();
+            External::parse(&format!("cmd=sh {},depth=1", path.display())).unwrap()
+        };
+
+        let mut a = Engine::parse("name=a,depth=1,hash=1").unwrap();
+        let mut same = fake("same", "echo 'bestmove e2e4'");
+        let game = play_game(&tables, position::START_FEN, &mut same, &mut a, None);
+        assert_eq!(game.tag("White"), Some("same"));
+        assert_eq!(game.line.moves.len(), 2);
+        assert_eq!(game.tag("Termination"), Some("rules infraction"));
+        assert_eq!(game.result, "0-1");
+        assert!(game.to_string().contains("{same played e2e4} 0-1"));
+
+        let mut silent = fake("silent", "exit");
+        let game = play_game(&tables, position::START_FEN, &mut a, &mut silent, None);
+        assert_eq!(game.line.moves.len(), 1);
+        assert_eq!(game.tag("Termination"), Some("rules infraction"));
+        assert_eq!(game.result, "1-0");
+        assert!(game.to_string().contains("{silent played nothing} 1-0"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_book() {
+        let tables = new_tables();
+        let start = Position::from_fen(&tables, position::START_FEN);
+        let castle = Position::from_fen(&tables, "r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1");
+
+        // keys from the Polyglot book format specification
+        let key = |fen| book::key(&Position::from_fen(&tables, fen).board);
+        assert_eq!(book::key(&start.board), 0x463b96181691fc9c);
+        // e4 d5 e5 f5
+        assert_eq!(key("rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3"), 0x22a48b5a8e47ff78);
+        // e4 d5 e5 f5 Ke2
+        assert_eq!(key("rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPPKPPP/RNBQ1BNR b kq - 0 3"), 0x652a607ca3f242c1);
+        // a4 b5 h4 b4 c4 bxc3 Ra3
+        assert_eq!(key("rnbqkbnr/p1pppppp/8/8/P6P/R1p5/1P1PPPP1/1NBQKBNR b Kkq - 0 4"), 0x5c3f9b829b279560);
+
+        // e4 is not an en passant position for polyglot, e4 d5 e5 f5 is
+        assert_eq!(key("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1"), 0x823c9b50fd114196);
+        assert_eq!(key("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1"), 0x823c9b50fd114196);
+        assert_ne!(key("rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq - 0 3"), 0x22a48b5a8e47ff78);
+
+        let mut data = Vec::new();
+        for (pos, mov, weight) in [
+            (&start, 0o1434u16, 1u16),  // e2e4
+            (&start, 0o1333, 3),        // d2d4
+            (&start, 0o0000, 5),        // a1a1, not legal
+            (&castle, 0o7477, 2),       // e8h8
+            (&castle, 0o7470, 1),       // e8a8
+        ].iter() {
+            data.extend_from_slice(&book::key(&pos.board).to_be_bytes());
+            data.extend_from_slice(&mov.to_be_bytes());
+            data.extend_from_slice(&weight.to_be_bytes());
+            data.extend_from_slice(&[0; 4]);
+        }
+
+        let mut book = book::Book::new(&data);
+        assert_eq!(book.probe(&start), vec![(Basic(12, 28), 1), (Basic(11, 27), 3)]);
+        assert_eq!(book.probe(&castle), vec![(Castle_king, 2), (Castle_queen, 1)]);
+
+        book.best = true;
+        assert_eq!(book.choose(&start), Some(Basic(11, 27)));
+        book.best = false;
+        for _ in 0..20 {
+            assert!(book.choose(&castle).is_some());
+        }
+
+        book.depth = 1;
+        assert_eq!(book.choose(&castle), None);
+        assert!(book.choose(&start).is_some());
+    }
+
+    #[test]
+    fn test_syzygy() {
+        use crate::syzygy::{Probe, Syzygy, Wdl, TB_WIN};
+        use std::sync::Arc;
+
+        // whoever has the queen wins
+        struct Queen;
+        impl Probe for Queen {
+            fn max_pieces(&self) -> usize {3}
+            fn probe_wdl(&self, pos: &Position) -> Option<Wdl> {
+                let queen = pos.board.rook & pos.board.bishop;
+                Some(if queen & pos.board.curr != 0 {Wdl::Win} else if queen != 0 {Wdl::Loss} else {Wdl::Draw})
+            }
+            fn probe_dtz(&self, pos: &Position) -> Option<i32> {
+                Some(match self.probe_wdl(pos)? {Wdl::Win => 10, Wdl::Loss => -10, _ => 0})
+            }
+        }
+
+        let tables = new_tables();
+        let pos = Position::from_fen(&tables, "7k/8/6K1/8/8/8/Q7/8 w - - 0 1");
+
+        assert_eq!(syzygy::root_move(&Queen, &pos), Some((Basic(8, 56), Wdl::Win)));
+        assert_eq!(syzygy::root_move(&Queen, &Position::from_fen(&tables, position::START_FEN)), None);
+
+        let mut search = Search::new(SearchLimits {depth: Some(3), ..SearchLimits::default()});
+        search.tb = Some(Arc::new(Queen));
+        assert_eq!(search.run(pos.clone()), (Some(Basic(8, 56)), TB_WIN));
+        assert_eq!(search.alphabeta(&mut pos.clone(), -MATE, MATE, 3, 1), TB_WIN - 1);
+
+        let dir = std::env::temp_dir().join(format!("syzygy_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["KQvK.rtbw", "KQvK.rtbz", "KRPvKR.rtbw", "notes.txt"].iter() {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+        let tb = Syzygy::open(dir.to_str().unwrap()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(tb.len(), 3);
+        assert_eq!(tb.max_pieces(), 5);
+    }
+
+    // Writes a KQvK table, with each part given by its flags and values. A
+    // part with one value is stored as a single value. Otherwise values get
+    // 6 bit codes, except the most common one, which gets a 2 bit code, as
+    // do two of it in a row.
+    #[cfg(test)]
+    fn write_kqk(path: &std::path::Path, dtz: bool, parts: &[(u8, Vec<u8>)]) {
+        let mut out = if dtz {vec![0xd7, 0x66, 0x0c, 0xa5]} else {vec![0x71, 0xe8, 0x23, 0x5d]};
+        // split, the leading group first, then K, Q and k for both sides
+        out.extend_from_slice(&[1, 0x00, 0x66, 0x55, 0xee, 0]);
+
+        let mut parts_blocks = Vec::new();
+        for (flags, values) in parts.iter() {
+            let mut blocks: Vec<(Vec<bool>, usize)> = Vec::new();
+
+            if values.iter().all(|v| *v == values[0]) {
+                out.extend_from_slice(&[flags | 0x80, values[0]]);
+                parts_blocks.push(blocks);
+                continue;
+            }
+
+            let common = (0..32).max_by_key(|v| values.iter().filter(|x| *x == v).count()).unwrap();
+            let mut i = 0;
+            while i < values.len() {
+                let mut bits = Vec::new();
+                let mut count = 0;
+
+                while i < values.len() {
+                    let (code, len, n) =
+                        if values[i] == common && values.get(i + 1) == Some(&common) {
+                            (3, 2, 2)
+                        } else if values[i] == common {
+                            (2, 2, 1)
+                        } else {
+                            (values[i], 6, 1)
+                        };
+                    if bits.len() + len > 512 {
+                        break;
+                    }
+                    bits.extend((0..len).rev().map(|b| code >> b & 1 == 1));
+                    i += n;
+                    count += n;
+                }
+                blocks.push((bits, count));
+            }
+
+            // 64 byte blocks, a sparse entry every 1024 values, codes of 2 to
+            // 6 bits with 32 of 6 bits and 2 of 2, and 34 symbols
+            out.extend_from_slice(&[*flags, 6, 10, 0]);
+            out.extend_from_slice(&(blocks.len() as u32).to_le_bytes());
+            out.extend_from_slice(&[6, 2]);
+            for lowest in [32u16, 32, 32, 32, 0].iter() {
+                out.extend_from_slice(&lowest.to_le_bytes());
+            }
+            out.extend_from_slice(&34u16.to_le_bytes());
+            for sym in 0..34 {
+                let (left, right) =
+                    match sym {
+                        32 => (common as usize, 0xfff),
+                        33 => (32, 32),
+                        _ => (sym, 0xfff),
+                    };
+                out.extend_from_slice(&[left as u8, (left >> 8 | right << 4) as u8, (right >> 4) as u8]);
+            }
+            parts_blocks.push(blocks);
+        }
+
+        for blocks in parts_blocks.iter().filter(|b| !b.is_empty()) {
+            let starts: Vec<usize> = blocks.iter().scan(0, |s, b| {*s += b.1; Some(*s - b.1)}).collect();
+            let total: usize = blocks.iter().map(|b| b.1).sum();
+
+            for k in 0..(total + 1023) / 1024 {
+                let idx = k * 1024 + 512;
+                let block = starts.iter().rposition(|s| *s <= idx).unwrap();
+                out.extend_from_slice(&(block as u32).to_le_bytes());
+                out.extend_from_slice(&((idx - starts[block]) as u16).to_le_bytes());
+            }
+        }
+        for blocks in parts_blocks.iter() {
+            for (_, count) in blocks.iter() {
+                out.extend_from_slice(&(*count as u16 - 1).to_le_bytes());
+            }
+        }
+        for blocks in parts_blocks.iter() {
+            while out.len() % 64 != 0 {
+                out.push(0);
+            }
+            for (bits, _) in blocks.iter() {
+                let mut block = [0u8; 64];
+                for (j, _) in bits.iter().enumerate().filter(|(_, b)| **b) {
+                    block[j / 8] |= 0x80 >> (j % 8);
+                }
+                out.extend_from_slice(&block);
+            }
+        }
+
+        std::fs::write(path, out).unwrap();
+    }
+
+    #[test]
+    fn test_syzygy_tables() {
+        use crate::syzygy::{Probe, Syzygy, Wdl, TB_WIN};
+        use std::sync::Arc;
+
+        let tables = new_tables();
+
+        // KQvK by retrograde analysis, with the white king, queen and black
+        // king on wk, wq and bk: plies to mate with white to move, plies to
+        // being mated with black to move, and black's stalemates
+        let near = |a: usize, b: usize| ((a % 8) as i32 - (b % 8) as i32).abs() <= 1 &&
+                                        ((a / 8) as i32 - (b / 8) as i32).abs() <= 1;
+        let attacks = |q: usize, blockers: u64| {
+            let mut out = 0u64;
+            for (dx, dy) in [(1, 0), (-1, 0), (0, 1), (0, -1), (1, 1), (1, -1), (-1, 1), (-1, -1)].iter() {
+                let (mut x, mut y) = ((q % 8) as i32 + dx, (q / 8) as i32 + dy);
+                while x >= 0 && x < 8 && y >= 0 && y < 8 {
+                    let s = (x + 8 * y) as usize;
+                    out |= 1 << s;
+                    if blockers & (1 << s) != 0 {
+                        break;
+                    }
+                    x += dx;
+                    y += dy;
+                }
+            }
+            out
+        };
+        let index = |wk: usize, wq: usize, bk: usize| (wk * 64 + wq) * 64 + bk;
+        let legal = |wk: usize, wq: usize, bk: usize| wk != wq && wq != bk && !near(wk, bk);
+        let in_check = |wk: usize, wq: usize, bk: usize| attacks(wq, 1 << wk) & (1 << bk) != 0;
+
+        // black's moves, and whether black can take the queen
+        let black_moves = |wk: usize, wq: usize, bk: usize| {
+            let guarded = attacks(wq, 1 << wk);
+            let moves: Vec<usize> = (0..64)
+                .filter(|s| near(*s, bk) && *s != bk && *s != wq && !near(*s, wk) && guarded & (1 << s) == 0)
+                .map(|s| index(wk, wq, s))
+                .collect();
+            (moves, near(wq, bk) && !near(wq, wk))
+        };
+        let white_moves = |wk: usize, wq: usize, bk: usize| {
+            let king = (0..64).filter(|s| near(*s, wk) && *s != wk && *s != wq && !near(*s, bk)).map(|s| index(s, wq, bk));
+            let queen = (0..64).filter(|s| attacks(wq, 1 << wk | 1 << bk) & (1 << s) != 0 && *s != wk && *s != bk)
+                .map(|s| index(wk, s, bk));
+            king.chain(queen).collect::<Vec<_>>()
+        };
+
+        let all: Vec<(usize, usize, usize)> = (0..64 * 64 * 64)
+            .map(|i| (i / 4096, i / 64 % 64, i % 64))
+            .filter(|(wk, wq, bk)| legal(*wk, *wq, *bk))
+            .collect();
+        let mut white = vec![None; 64 * 64 * 64];
+        let mut black = vec![None; 64 * 64 * 64];
+        let mut stalemate = vec![false; 64 * 64 * 64];
+
+        for &(wk, wq, bk) in all.iter() {
+            let (moves, take) = black_moves(wk, wq, bk);
+            if moves.is_empty() && !take {
+                if in_check(wk, wq, bk) {
+                    black[index(wk, wq, bk)] = Some(0);
+                } else {
+                    stalemate[index(wk, wq, bk)] = true;
+                }
+            }
+        }
+        for ply in (1..).step_by(2) {
+            let mut changed = false;
+
+            for &(wk, wq, bk) in all.iter() {
+                let i = index(wk, wq, bk);
+                if white[i].is_none() && !in_check(wk, wq, bk) &&
+                   white_moves(wk, wq, bk).iter().any(|j| black[*j] == Some(ply - 1))
+                {
+                    white[i] = Some(ply);
+                    changed = true;
+                }
+            }
+            for &(wk, wq, bk) in all.iter() {
+                let i = index(wk, wq, bk);
+                let (moves, take) = black_moves(wk, wq, bk);
+                if black[i].is_none() && !take && !moves.is_empty() && moves.iter().all(|j| white[*j].is_some()) {
+                    black[i] = Some(moves.iter().map(|j| white[*j].unwrap()).max().unwrap() + 1);
+                }
+            }
+
+            if !changed {
+                break;
+            }
+        }
+
+        // the longest win is a mate in 10
+        assert_eq!(white.iter().max(), Some(&Some(19)));
+
+        let board = |wk: usize, wq: usize, bk: usize, black_to_move: bool| {
+            let mut b = Board::new();
+            b.set_loc(wk as u8, Piece::King, false);
+            b.set_loc(wq as u8, Piece::Queen, false);
+            b.set_loc(bk as u8, Piece::King, true);
+            b.key = b.gen_key();
+            if black_to_move {
+                b.invert();
+            }
+            b
+        };
+
+        // The index only depends on the header, so tables of single values
+        // give where each position goes. White to move always wins, black
+        // to move loses unless stalemated, and dtz is stored in plies for
+        // white to move only.
+        let dir = std::env::temp_dir().join(format!("syzygy_kqk_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (wdl_path, dtz_path) = (dir.join("KQvK.rtbw"), dir.join("KQvK.rtbz"));
+        write_kqk(&wdl_path, false, &[(0, vec![4]), (0, vec![0])]);
+        write_kqk(&dtz_path, true, &[(4, vec![0])]);
+
+        let tb = Syzygy::open(dir.to_str().unwrap()).unwrap();
+        let mut wdl_values = vec![0; 31332];
+        let mut dtz_values = vec![0; 31332];
+        for &(wk, wq, bk) in all.iter() {
+            let i = index(wk, wq, bk);
+            if let Some(ply) = white[i] {
+                dtz_values[tb.index(&board(wk, wq, bk, false), true).unwrap() as usize] = ply as u8 - 1;
+            }
+            if stalemate[i] {
+                wdl_values[tb.index(&board(wk, wq, bk, true), false).unwrap() as usize] = 2;
+            }
+        }
+
+        write_kqk(&wdl_path, false, &[(0, vec![4]), (0, wdl_values)]);
+        write_kqk(&dtz_path, true, &[(4, dtz_values)]);
+        let tb = Syzygy::open(dir.to_str().unwrap()).unwrap();
+
+        let probe = |fen: &str| {
+            let pos = Position::from_fen(&tables, fen);
+            (tb.probe_wdl(&pos), tb.probe_dtz(&pos))
+        };
+
+        // mate in one, stalemate, a queen left to be taken, and mates for
+        // both colors
+        assert_eq!(probe("k7/8/1K6/8/8/8/8/7Q w - - 0 1"), (Some(Wdl::Win), Some(1)));
+        assert_eq!(probe("k7/2Q5/1K6/8/8/8/8/8 b - - 0 1"), (Some(Wdl::Draw), Some(0)));
+        assert_eq!(probe("8/8/8/8/8/8/1kQ5/4K3 b - - 0 1"), (Some(Wdl::Draw), Some(0)));
+        assert_eq!(probe("k6Q/8/1K6/8/8/8/8/8 b - - 0 1"), (Some(Wdl::Loss), Some(-1)));
+        assert_eq!(probe("8/8/8/8/8/1k6/8/K6q w - - 0 1"), (Some(Wdl::Loss), Some(-1)));
+
+        // every hundredth position of both sides
+        for &(wk, wq, bk) in all.iter().step_by(100) {
+            let i = index(wk, wq, bk);
+            let pos = |black_to_move| {
+                let mut pos = Position::new(&tables);
+                pos.board = board(wk, wq, bk, black_to_move);
+                pos
+            };
+
+            if let Some(ply) = white[i] {
+                assert_eq!(tb.probe_dtz(&pos(false)), Some(ply as i32));
+            }
+            if let Some(ply) = black[i] {
+                assert_eq!(tb.probe_wdl(&pos(true)), Some(Wdl::Loss));
+                // being mated counts as -1
+                assert_eq!(tb.probe_dtz(&pos(true)), Some(-(ply as i32).max(1)));
+            }
+        }
+
+        // the search plays the fastest mate
+        let pos = Position::from_fen(&tables, "8/8/8/8/3k4/8/8/KQ6 w - - 0 1");
+        let dtz = tb.probe_dtz(&pos).unwrap();
+        let mut search = Search::new(SearchLimits {depth: Some(3), ..SearchLimits::default()});
+        search.tb = Some(Arc::new(tb));
+        let (m, score) = search.run(pos.clone());
+        assert_eq!(score, TB_WIN);
+
+        let mut after = pos.clone();
+        after.do_move(&m.unwrap());
+        after.board.invert();
+        assert_eq!(search.tb.as_ref().unwrap().probe_dtz(&after), Some(1 - dtz));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // Known results from the real KQvK and KRvK tables, in the directory
+    // given by SYZYGY_PATH.
+    #[test]
+    fn test_syzygy_real_tables() {
+        use crate::syzygy::{Probe, Syzygy, Wdl};
+
+        let dir = match std::env::var("SYZYGY_PATH") {
+            Ok(dir) => dir,
+            Err(_) => return eprintln!("SYZYGY_PATH not set, skipping the real tables"),
+        };
+        for name in ["KQvK.rtbw", "KQvK.rtbz", "KRvK.rtbw", "KRvK.rtbz"].iter() {
+            assert!(std::path::Path::new(&dir).join(name).exists(), "{} missing from {}", name, dir);
+        }
+
+        let tables = new_tables();
+        let tb = Syzygy::open(&dir).unwrap();
+        let probe = |fen: &str| {
+            let pos = Position::from_fen(&tables, fen);
+            (tb.probe_wdl(&pos), tb.probe_dtz(&pos))
+        };
+
+        assert_eq!(probe("k7/8/1K6/8/8/8/8/7Q w - - 0 1"), (Some(Wdl::Win), Some(1)));
+        assert_eq!(probe("k7/2Q5/1K6/8/8/8/8/8 b - - 0 1"), (Some(Wdl::Draw), Some(0)));
+        assert_eq!(probe("k6Q/8/1K6/8/8/8/8/8 b - - 0 1"), (Some(Wdl::Loss), Some(-1)));
+        assert_eq!(probe("4k3/8/4K3/8/8/8/8/R7 w - - 0 1"), (Some(Wdl::Win), Some(1)));
+        assert_eq!(probe("4k3/8/8/8/8/8/8/R3K3 w - - 0 1").0, Some(Wdl::Win));
+        assert_eq!(probe("8/8/8/8/8/2k5/1R6/K7 b - - 0 1").0, Some(Wdl::Loss));
+        assert_eq!(probe("8/8/8/8/8/2k5/1R6/7K b - - 0 1"), (Some(Wdl::Draw), Some(0)));
+    }
+
     #[bench]
     fn bench_board_invert(b: &mut Bencher) {
         let mut board = Board::from_fen(TEST_BOARD);
@@ -347,11 +1730,48 @@ mod tests {
         b.iter(|| eval::eval(test::black_box(&mut position)));
     }
 
+    #[bench]
+    fn bench_child_clone(b: &mut Bencher) {
+        let tables = new_tables();
+        let mut pos = Position::from_fen(&tables, "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+        pos.set_moves();
+        let moves = pos.gen_moves();
+
+        b.iter(|| {
+            for m in moves.iter() {
+                let mut p = pos.clone();
+                p.do_move(m);
+                p.board.invert();
+                test::black_box(&p);
+            }
+        });
+    }
+
+    #[bench]
+    fn bench_child_make_unmake(b: &mut Bencher) {
+        let tables = new_tables();
+        let mut pos = Position::from_fen(&tables, "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+        pos.set_moves();
+        let moves = pos.gen_moves();
+
+        b.iter(|| {
+            for m in moves.iter() {
+                let undo = pos.do_move(m);
+                pos.board.invert();
+                test::black_box(&pos);
+                pos.board.invert();
+                pos.unmake_move(m, undo);
+            }
+        });
+    }
+
     #[bench]
     fn bench_alphabeta(b: &mut Bencher) {
         let tables = new_tables();
         let mut position = Position::from_fen(&tables, "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
 
-        b.iter(|| best_move(test::black_box(position.clone()), 1, None));
+        let mut search = Search::new(SearchLimits::default());
+
+        b.iter(|| search.best_move(test::black_box(position.clone()), 1, None));
     }
 }
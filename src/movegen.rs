@@ -11,6 +11,17 @@ pub enum Move {
 pub use Move::*;
 pub use crate::board::{Board, Piece, Piece::*};
 use crate::gen_table::*;
+use crate::zobrist;
+
+// what Board::do_move can't recover on its own when taking a move back
+#[derive(Clone, Debug)]
+pub struct Undo {
+    pub captured: Piece,
+    pub castle_curr: [bool; 2],
+    pub castle_other: [bool; 2],
+    pub ep: u64,
+    pub key: u64,
+}
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct Moves {
@@ -193,20 +204,31 @@ impl Board {
         )
     }
 
-    pub fn do_move(&mut self, m: &Move) {
+    pub fn do_move(&mut self, m: &Move) -> Undo {
+        let undo = Undo {
+            captured:
+                match m {
+                    Basic(_, to) | Promotion(_, _, to) => self.get_loc_piece(*to),
+                    _ => Empty,
+                },
+            castle_curr: self.castle_curr,
+            castle_other: self.castle_other,
+            ep: self.pawn & 0xff000000000000ff,
+            key: self.key,
+        };
+
+        self.key ^= zobrist::castle_key(self) ^ zobrist::ep_key(self.pawn);
+
         match m {
             Basic(from, to) => {
                 let piece = self.get_loc_piece(*from);
+                self.key ^= self.piece_key(piece, false, *from) ^
+                            self.piece_key(piece, false, *to) ^
+                            self.piece_key(self.get_loc_piece(*to), true, *to);
                 self.clear_loc(*to);
                 self.set_loc(*to, piece, false);
                 self.clear_loc(*from);
 
-                if *to == 0 && self.get_loc_piece(0) == Rook {
-                    self.castle_other[0] = false;
-                }
-                if *to == 7 && self.get_loc_piece(7) == Rook {
-                    self.castle_other[1] = false;
-                }
                 if piece == King {
                     self.castle_curr[0] = false;
                     self.castle_curr[1] = false;
@@ -220,11 +242,18 @@ impl Board {
             },
             // file of pawn which took, file of taken pawn
             En_passant(fromfile, tofile) => {
+                self.key ^= self.piece_key(Pawn, false, fromfile + 32) ^
+                            self.piece_key(Pawn, false, tofile + 40) ^
+                            self.piece_key(Pawn, true , tofile + 32);
                 self.clear_loc(tofile + 32);
                 self.clear_loc(fromfile + 32);
                 self.set_loc(tofile + 40, Pawn, false);
             },
             Castle_king => {
+                self.key ^= self.piece_key(King, false, 4) ^
+                            self.piece_key(King, false, 6) ^
+                            self.piece_key(Rook, false, 7) ^
+                            self.piece_key(Rook, false, 5);
                 self.clear_loc(4);
                 self.clear_loc(7);
                 self.set_loc(6, King, false);
@@ -233,6 +262,10 @@ impl Board {
                 self.castle_curr[1] = false;
             },
             Castle_queen => {
+                self.key ^= self.piece_key(King, false, 4) ^
+                            self.piece_key(King, false, 2) ^
+                            self.piece_key(Rook, false, 0) ^
+                            self.piece_key(Rook, false, 3);
                 self.clear_loc(4);
                 self.clear_loc(0);
                 self.set_loc(2, King, false);
@@ -241,11 +274,74 @@ impl Board {
                 self.castle_curr[1] = false;
             },
             Promotion(p, from, to) => {
+                self.key ^= self.piece_key(Pawn, false, *from) ^
+                            self.piece_key(*p  , false, *to) ^
+                            self.piece_key(self.get_loc_piece(*to), true, *to);
+                self.clear_loc(*to);
                 self.set_loc(*to, *p, false);
                 self.clear_loc(*from);
             },
         }
+        // a capture on the other player's rook squares loses their castle
+        if self.curr & (1 << 56) != 0 {
+            self.castle_other[0] = false;
+        }
+        if self.curr & (1 << 63) != 0 {
+            self.castle_other[1] = false;
+        }
         self.pawn &= 0x00ffffffffffffff;
+
+        // a double push next to an enemy pawn leaves an en-passant code on
+        // rank 1, which becomes rank 8 once the board is inverted
+        if let Basic(from, to) = m {
+            let bit = 1u64 << to;
+            let beside = (bit << 1 & !0x0101010101010101) | (bit >> 1 & !0x8080808080808080);
+
+            if *to == from + 16 && self.pawns() & bit != 0 &&
+               self.pawns() & self.other & beside != 0
+            {
+                self.pawn |= 1 << (to % 8);
+            }
+        }
+        self.key ^= zobrist::castle_key(self);
+        undo
+    }
+
+    pub fn unmake_move(&mut self, m: &Move, undo: &Undo) {
+        match m {
+            Basic(from, to) => {
+                let piece = self.get_loc_piece(*to);
+                self.clear_loc(*to);
+                self.set_loc(*from, piece, false);
+                self.set_loc(*to, undo.captured, true);
+            },
+            En_passant(fromfile, tofile) => {
+                self.clear_loc(tofile + 40);
+                self.set_loc(fromfile + 32, Pawn, false);
+                self.set_loc(tofile + 32, Pawn, true);
+            },
+            Castle_king => {
+                self.clear_loc(6);
+                self.clear_loc(5);
+                self.set_loc(4, King, false);
+                self.set_loc(7, Rook, false);
+            },
+            Castle_queen => {
+                self.clear_loc(2);
+                self.clear_loc(3);
+                self.set_loc(4, King, false);
+                self.set_loc(0, Rook, false);
+            },
+            Promotion(_, from, to) => {
+                self.clear_loc(*to);
+                self.set_loc(*from, Pawn, false);
+                self.set_loc(*to, undo.captured, true);
+            },
+        }
+        self.castle_curr = undo.castle_curr;
+        self.castle_other = undo.castle_other;
+        self.pawn = self.pawn & 0x00ffffffffffff00 | undo.ep;
+        self.key = undo.key;
     }
 
     pub fn get_random_move(&self, moves: &Moves) -> Move {
@@ -20,7 +20,7 @@ impl Moves {
         self.others.clear();
     }
 
-    fn loc_to_string(mut loc: u8, inverted: bool) -> String {
+    pub fn loc_to_string(mut loc: u8, inverted: bool) -> String {
         if inverted {
             Board::invert_loc(&mut loc);
         }
@@ -58,13 +58,13 @@ impl Moves {
         out
     }
 
-    pub fn string_to_move(s: &str, board: &Board) -> Move {
-        let mut i = s.chars();
+    pub fn string_to_move(s: &str, board: &Board) -> Option<Move> {
+        let mut i = s.trim().chars();
 
-        let x1 = i.next().unwrap().to_digit(18).unwrap() - 10;
-        let y1 = i.next().unwrap().to_digit( 9).unwrap() - 1;
-        let x2 = i.next().unwrap().to_digit(18).unwrap() - 10;
-        let y2 = i.next().unwrap().to_digit( 9).unwrap() - 1;
+        let x1 = i.next()?.to_digit(18).filter(|n| *n >= 10 && *n < 18)? - 10;
+        let y1 = i.next()?.to_digit( 9).filter(|n| *n >= 1)? - 1;
+        let x2 = i.next()?.to_digit(18).filter(|n| *n >= 10 && *n < 18)? - 10;
+        let y2 = i.next()?.to_digit( 9).filter(|n| *n >= 1)? - 1;
         let mut out1 = (x1 + y1 * 8) as u8;
         let mut out2 = (x2 + y2 * 8) as u8;
 
@@ -73,38 +73,38 @@ impl Moves {
             Board::invert_loc(&mut out2);
         }
 
-        let loc1 = (x1 + y1 * 8) as isize;
-        let loc2 = (x2 + y2 * 8) as isize;
+        let loc1 = out1 as isize;
+        let loc2 = out2 as isize;
 
-        match board.get_loc_piece(loc1 as u8) {
+        match board.get_loc_piece(out1) {
             King => {
                 if loc2 - loc1 == 2 {
-                    return Castle_king;
+                    return Some(Castle_king);
                 }
                 if loc1 - loc2 == 2 {
-                    return Castle_queen;
+                    return Some(Castle_queen);
                 }
             },
             Pawn => {
                 if loc2 >= 56 {
                     let piece =
-                        match i.next().unwrap() {
+                        match i.next()? {
                             'q' => Queen ,
                             'n' => Knight,
                             'r' => Rook  ,
                             'b' => Bishop,
-                            _ => panic!("Invalid promotion!")
+                            _ => return None
                         };
-                    return Promotion(piece, out1, out2);
+                    return Some(Promotion(piece, out1, out2));
                 }
-                if (loc2 - loc1) % 8 != 0 && board.get_loc_piece(loc2 as u8) == Empty {
-                    return En_passant(out1 % 8, out2 % 8);
+                if (loc2 - loc1) % 8 != 0 && board.get_loc_piece(out2) == Empty {
+                    return Some(En_passant(out1 % 8, out2 % 8));
                 }
             },
             _ => {}
         }
 
-        Basic(out1, out2)
+        Some(Basic(out1, out2))
     }
 }
 
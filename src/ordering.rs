@@ -0,0 +1,99 @@
+use crate::board::{Board, Piece::*};
+use crate::eval::piece_value;
+use crate::movegen::Move;
+use crate::san::move_squares;
+use crate::search::{captured_value, is_tactical};
+
+// sort keys of the hash move, tactical moves and killers, above any history
+const HASH_MOVE: i32 = 1 << 30;
+const TACTICAL: i32 = 1 << 28;
+const KILLER: i32 = 1 << 27;
+
+// history scores are halved when one gets this large
+const HISTORY_MAX: i32 = 1 << 20;
+
+// Most valuable victim, least valuable attacker: bigger captures first, and
+// of those the ones made with the cheapest piece.
+pub fn mvv_lva(board: &Board, m: &Move) -> i32 {
+    let (from, _) = move_squares(m);
+    let attacker = match board.get_loc_piece(from) {
+        King => 0,
+        p => piece_value(p),
+    };
+
+    captured_value(board, m) * 16 - attacker / 16
+}
+
+// Killers are quiet moves that caused a cutoff at the same ply in another
+// branch. History counts the cutoffs of quiet moves by squares, weighted by
+// the depth they happened at.
+pub struct MoveOrder {
+    killers: Vec<[Option<Move>; 2]>,
+    history: Vec<i32>,
+}
+
+impl MoveOrder {
+    pub fn new() -> MoveOrder {
+        MoveOrder {
+            killers: Vec::new(),
+            history: vec![0; 64 * 64],
+        }
+    }
+
+    // forgets the killers and ages the history before a new search
+    pub fn new_search(&mut self) {
+        self.killers.clear();
+        for h in self.history.iter_mut() {
+            *h /= 2;
+        }
+    }
+
+    fn history_index(m: &Move) -> usize {
+        let (from, to) = move_squares(m);
+        from as usize * 64 + to as usize
+    }
+
+    fn is_killer(&self, m: &Move, ply: usize) -> Option<usize> {
+        self.killers.get(ply)?.iter().position(|k| k.as_ref() == Some(m))
+    }
+
+    pub fn score(&self, board: &Board, m: &Move, tt_move: Option<&Move>, ply: usize) -> i32 {
+        if tt_move == Some(m) {
+            HASH_MOVE
+        } else if is_tactical(board, m) {
+            TACTICAL + mvv_lva(board, m)
+        } else if let Some(i) = self.is_killer(m, ply) {
+            KILLER - i as i32
+        } else {
+            self.history[MoveOrder::history_index(m)]
+        }
+    }
+
+    pub fn sort(&self, board: &Board, moves: &mut Vec<Move>, tt_move: Option<&Move>, ply: usize) {
+        moves.sort_by_cached_key(|m| -self.score(board, m, tt_move, ply));
+    }
+
+    // records a beta cutoff by m
+    pub fn cutoff(&mut self, board: &Board, m: &Move, depth: usize, ply: usize) {
+        if is_tactical(board, m) {
+            return;
+        }
+
+        if self.killers.len() <= ply {
+            self.killers.resize(ply + 1, [None, None]);
+        }
+        let killers = &mut self.killers[ply];
+        if killers[0].as_ref() != Some(m) {
+            killers[1] = killers[0].take();
+            killers[0] = Some(m.clone());
+        }
+
+        let h = &mut self.history[MoveOrder::history_index(m)];
+        *h += (depth * depth) as i32;
+        if *h >= HISTORY_MAX {
+            for h in self.history.iter_mut() {
+                *h /= 2;
+            }
+        }
+    }
+}
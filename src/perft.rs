@@ -0,0 +1,51 @@
+use crate::movegen::{Moves, Move};
+use crate::position::Position;
+
+pub fn perft(pos: &Position, depth: usize) -> usize {
+    if depth == 0 {
+        return 1;
+    }
+
+    let mut pos = pos.clone();
+    pos.set_moves();
+    let moves = pos.gen_moves();
+
+    // bulk counting: the number of leaves is just the number of legal moves
+    if depth == 1 {
+        return moves.len();
+    }
+
+    let mut out = 0;
+    for m in moves {
+        let mut p = pos.clone();
+        p.do_move(&m);
+        p.board.invert();
+        out += perft(&p, depth - 1);
+    }
+    out
+}
+
+pub fn divide(pos: &Position, depth: usize) -> Vec<(Move, usize)> {
+    let mut pos = pos.clone();
+    pos.set_moves();
+
+    pos.gen_moves().into_iter().map(|m| {
+        let mut p = pos.clone();
+        p.do_move(&m);
+        p.board.invert();
+        let n = perft(&p, depth.max(1) - 1);
+        (m, n)
+    }).collect()
+}
+
+pub fn print_divide(pos: &Position, depth: usize) {
+    let mut total = 0;
+
+    for (m, n) in divide(pos, depth) {
+        println!("{}: {}", Moves::move_to_string(&m, pos.board.inverted), n);
+        total += n;
+    }
+
+    println!();
+    println!("Nodes searched: {}", total);
+}
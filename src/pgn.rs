@@ -0,0 +1,459 @@
+use crate::board::FenError;
+use crate::gen_table::Tables;
+use crate::movegen::Move;
+use crate::position::{Position, START_FEN};
+use crate::san::{SanError, move_to_san, san_to_move};
+
+use std::fmt;
+use std::iter::Peekable;
+use std::vec::IntoIter;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PgnError {
+    Tag,
+    Unterminated,
+    Unexpected(String),
+    Fen(FenError),
+    Move(String, SanError),
+}
+
+impl fmt::Display for PgnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PgnError::Tag           => write!(f, "malformed tag pair"),
+            PgnError::Unterminated  => write!(f, "unterminated comment or variation"),
+            PgnError::Unexpected(s) => write!(f, "unexpected \"{}\"", s),
+            PgnError::Fen(e)        => write!(f, "bad FEN tag: {}", e),
+            PgnError::Move(s, e)    => write!(f, "{}: {}", s, e),
+        }
+    }
+}
+
+impl std::error::Error for PgnError {}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Tag(String, String),
+    Comment(String),
+    Nag(u8),
+    Open,
+    Close,
+    San(String),
+    Result(String),
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Line {
+    pub comment: Option<String>,
+    pub moves: Vec<Node>,
+}
+
+// a move with its annotations; each variation replaces this move
+#[derive(Clone, Debug, PartialEq)]
+pub struct Node {
+    pub mov: Move,
+    pub san: String,
+    pub nags: Vec<u8>,
+    pub comment: Option<String>,
+    pub variations: Vec<Line>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Game {
+    pub tags: Vec<(String, String)>,
+    pub line: Line,
+    pub result: String,
+}
+
+// "1-0", "0-1" or "1/2-1/2" for a result of Position::test_endgame, "*" if
+// the game is not over
+pub fn result_string(outcome: Option<usize>) -> &'static str {
+    match outcome {
+        Some(0) => "0-1",
+        Some(1) => "1/2-1/2",
+        Some(2) => "1-0",
+        _ => "*",
+    }
+}
+
+impl Game {
+    pub fn new(fen: &str) -> Game {
+        let mut out = Game {
+            tags: Vec::new(),
+            line: Line::default(),
+            result: "*".to_string(),
+        };
+
+        for (name, value) in [
+            ("Event", "?"),
+            ("Site", "?"),
+            ("Date", &today()),
+            ("Round", "?"),
+            ("White", "?"),
+            ("Black", "?"),
+            ("Result", "*"),
+        ].iter() {
+            out.set_tag(name, value);
+        }
+
+        if fen != START_FEN {
+            out.set_tag("SetUp", "1");
+            out.set_tag("FEN", fen);
+        }
+
+        out
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    pub fn set_tag(&mut self, name: &str, value: &str) {
+        match self.tags.iter_mut().find(|(n, _)| n == name) {
+            Some(t) => t.1 = value.to_string(),
+            None => self.tags.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    pub fn set_result(&mut self, result: &str) {
+        self.result = result.to_string();
+        self.set_tag("Result", result);
+    }
+
+    // appends m, played in pos, to the main line
+    pub fn push(&mut self, pos: &Position, m: &Move) {
+        self.line.moves.push(Node {
+            mov: m.clone(),
+            san: move_to_san(pos, m),
+            nags: Vec::new(),
+            comment: None,
+            variations: Vec::new(),
+        });
+    }
+
+    pub fn start<'a>(&self, tables: &'a Tables) -> Result<Position<'a>, FenError> {
+        Position::try_from_fen(tables, self.tag("FEN").unwrap_or(START_FEN))
+    }
+
+    // the position after the last move of the main line
+    pub fn end<'a>(&self, tables: &'a Tables) -> Result<Position<'a>, FenError> {
+        let mut pos = self.start(tables)?;
+
+        for n in self.line.moves.iter() {
+            pos.do_move(&n.mov);
+            pos.board.invert();
+        }
+        pos.set_moves();
+
+        Ok(pos)
+    }
+
+    fn start_ply(&self) -> usize {
+        let fen: Vec<&str> = self.tag("FEN").unwrap_or(START_FEN).split_whitespace().collect();
+        let full_moves = fen.get(5).and_then(|w| w.parse().ok()).unwrap_or(1usize);
+
+        (full_moves.max(1) - 1) * 2 + (fen.get(1) == Some(&"b")) as usize
+    }
+}
+
+fn write_line(words: &mut Vec<String>, line: &Line, ply: usize) {
+    if let Some(c) = &line.comment {
+        words.push(format!("{{{}}}", c));
+    }
+
+    let mut number = true;
+    for (i, n) in line.moves.iter().enumerate() {
+        let ply = ply + i;
+
+        if ply % 2 == 0 {
+            words.push(format!("{}.", ply / 2 + 1));
+        } else if number {
+            words.push(format!("{}...", ply / 2 + 1));
+        }
+        number = false;
+
+        words.push(n.san.clone());
+        for nag in n.nags.iter() {
+            words.push(format!("${}", nag));
+        }
+
+        if let Some(c) = &n.comment {
+            words.push(format!("{{{}}}", c));
+            number = true;
+        }
+
+        for v in n.variations.iter() {
+            words.push("(".to_string());
+            write_line(words, v, ply);
+            words.push(")".to_string());
+            number = true;
+        }
+    }
+}
+
+impl fmt::Display for Game {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, value) in self.tags.iter() {
+            writeln!(f, "[{} \"{}\"]", name, value.replace('\\', "\\\\").replace('"', "\\\""))?;
+        }
+        writeln!(f)?;
+
+        let mut words = Vec::new();
+        write_line(&mut words, &self.line, self.start_ply());
+        words.push(self.result.clone());
+
+        // movetext lines are kept under 80 characters
+        let mut len = 0;
+        let mut open = false;
+        for w in words.iter().flat_map(|w| w.split_whitespace()) {
+            if len > 0 && len + 1 + w.len() > 79 {
+                writeln!(f)?;
+                len = 0;
+            } else if len > 0 && w != ")" && !open {
+                write!(f, " ")?;
+                len += 1;
+            }
+            write!(f, "{}", w)?;
+            len += w.len();
+            open = w == "(";
+        }
+        writeln!(f)
+    }
+}
+
+fn read_tag(chars: &mut Peekable<std::str::Chars>) -> Result<Token, PgnError> {
+    let mut name = String::new();
+    let mut value = String::new();
+
+    while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || *c == '_') {
+        name.push(c);
+    }
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+
+    if name.is_empty() || chars.next() != Some('"') {
+        return Err(PgnError::Tag);
+    }
+
+    loop {
+        match chars.next() {
+            Some('"') => break,
+            Some('\\') => value.push(chars.next().ok_or(PgnError::Tag)?),
+            Some(c) => value.push(c),
+            None => return Err(PgnError::Tag),
+        }
+    }
+
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+    if chars.next() != Some(']') {
+        return Err(PgnError::Tag);
+    }
+
+    Ok(Token::Tag(name, value))
+}
+
+fn suffix_nag(s: &str) -> Option<u8> {
+    match s {
+        "!"  => Some(1),
+        "?"  => Some(2),
+        "!!" => Some(3),
+        "??" => Some(4),
+        "!?" => Some(5),
+        "?!" => Some(6),
+        _ => None,
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, PgnError> {
+    let mut out = Vec::new();
+    let mut chars = text.chars().peekable();
+    let mut line_start = true;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => {
+                line_start = true;
+                continue;
+            },
+            // escaped lines and rest of line comments
+            '%' | ';' if c == ';' || line_start => {
+                let comment: String = std::iter::from_fn(|| chars.next_if(|c| *c != '\n')).collect();
+                if c == ';' {
+                    out.push(Token::Comment(comment.trim().to_string()));
+                }
+            },
+            '[' => out.push(read_tag(&mut chars)?),
+            '{' => {
+                let comment: String = std::iter::from_fn(|| chars.next_if(|c| *c != '}')).collect();
+                if chars.next().is_none() {
+                    return Err(PgnError::Unterminated);
+                }
+                out.push(Token::Comment(comment.split_whitespace().collect::<Vec<_>>().join(" ")));
+            },
+            '(' => out.push(Token::Open),
+            ')' => out.push(Token::Close),
+            '$' => {
+                let n: String = std::iter::from_fn(|| chars.next_if(|c| c.is_ascii_digit())).collect();
+                out.push(Token::Nag(n.parse().map_err(|_| PgnError::Unexpected(format!("${}", n)))?));
+            },
+            c if c.is_whitespace() => {},
+            c => {
+                let mut word = c.to_string();
+                word.extend(std::iter::from_fn(|| chars.next_if(|c| !c.is_whitespace() && !"{}()[];$".contains(*c))));
+
+                // move numbers such as "12." or "12..." may be attached to the move
+                let word =
+                    if word.starts_with(|c: char| c.is_ascii_digit()) && word.contains('.') {
+                        word.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.')
+                    } else {
+                        &word
+                    };
+
+                match word {
+                    "" => {},
+                    "1-0" | "0-1" | "1/2-1/2" | "*" => out.push(Token::Result(word.to_string())),
+                    _ => {
+                        let san = word.trim_end_matches(|c| c == '!' || c == '?');
+                        out.push(Token::San(san.to_string()));
+                        if let Some(n) = suffix_nag(&word[san.len()..]) {
+                            out.push(Token::Nag(n));
+                        }
+                    },
+                }
+            },
+        }
+        line_start = false;
+    }
+
+    Ok(out)
+}
+
+fn unexpected(t: &Token) -> PgnError {
+    PgnError::Unexpected(
+        match t {
+            Token::Tag(n, _) => format!("[{}", n),
+            Token::Comment(c) => format!("{{{}}}", c),
+            Token::Nag(n) => format!("${}", n),
+            Token::Open => "(".to_string(),
+            Token::Close => ")".to_string(),
+            Token::San(s) | Token::Result(s) => s.clone(),
+        })
+}
+
+fn add_comment(to: &mut Option<String>, comment: String) {
+    *to = match to.take() {
+        Some(c) => Some(c + " " + &comment),
+        None => Some(comment),
+    };
+}
+
+// parses moves starting from pos up to the end of the game, or up to the
+// closing parenthesis if this is a variation
+fn parse_line(tokens: &mut Peekable<IntoIter<Token>>, mut pos: Position, nested: bool) -> Result<Line, PgnError> {
+    let mut line = Line::default();
+    // position before the last move, which its variations start from
+    let mut prev: Option<Position> = None;
+
+    pos.set_moves();
+
+    loop {
+        match tokens.peek() {
+            None | Some(Token::Tag(_, _)) | Some(Token::Result(_)) if nested => return Err(PgnError::Unterminated),
+            None | Some(Token::Tag(_, _)) | Some(Token::Result(_)) => return Ok(line),
+            Some(Token::Close) if nested => {
+                tokens.next();
+                return Ok(line);
+            },
+            _ => {},
+        }
+
+        match tokens.next().unwrap() {
+            Token::Comment(c) => {
+                match line.moves.last_mut() {
+                    Some(n) => add_comment(&mut n.comment, c),
+                    None => add_comment(&mut line.comment, c),
+                }
+            },
+            Token::Nag(n) if !line.moves.is_empty() => line.moves.last_mut().unwrap().nags.push(n),
+            Token::Open if prev.is_some() => {
+                let v = parse_line(tokens, prev.clone().unwrap(), true)?;
+                line.moves.last_mut().unwrap().variations.push(v);
+            },
+            Token::San(s) => {
+                let m = san_to_move(&pos, &s).map_err(|e| PgnError::Move(s, e))?;
+                let san = move_to_san(&pos, &m);
+
+                prev = Some(pos.clone());
+                pos.do_move(&m);
+                pos.board.invert();
+                pos.set_moves();
+
+                line.moves.push(Node {
+                    mov: m,
+                    san,
+                    nags: Vec::new(),
+                    comment: None,
+                    variations: Vec::new(),
+                });
+            },
+            t => return Err(unexpected(&t)),
+        }
+    }
+}
+
+fn parse_game(tables: &Tables, tokens: &mut Peekable<IntoIter<Token>>) -> Result<Game, PgnError> {
+    let mut game = Game {
+        tags: Vec::new(),
+        line: Line::default(),
+        result: "*".to_string(),
+    };
+
+    while let Some(Token::Tag(_, _)) = tokens.peek() {
+        if let Some(Token::Tag(name, value)) = tokens.next() {
+            game.set_tag(&name, &value);
+        }
+    }
+
+    let pos = game.start(tables).map_err(PgnError::Fen)?;
+    game.line = parse_line(tokens, pos, false)?;
+
+    let result =
+        match tokens.next_if(|t| matches!(t, Token::Result(_))) {
+            Some(Token::Result(r)) => r,
+            _ => game.tag("Result").unwrap_or("*").to_string(),
+        };
+    game.set_result(&result);
+
+    Ok(game)
+}
+
+// parses every game in text
+pub fn parse(tables: &Tables, text: &str) -> Result<Vec<Game>, PgnError> {
+    let mut tokens = tokenize(text)?.into_iter().peekable();
+    let mut out = Vec::new();
+
+    while tokens.peek().is_some() {
+        out.push(parse_game(tables, &mut tokens)?);
+    }
+
+    Ok(out)
+}
+
+// the current UTC date as "YYYY.MM.DD"
+fn today() -> String {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64);
+
+    // days since 1970-01-01 to a civil date
+    let z = secs / 86400 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 {mp + 3} else {mp - 9};
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    format!("{:04}.{:02}.{:02}", year, month, day)
+}
@@ -1,10 +1,25 @@
-use crate::board::{Board, Piece, Piece::*};
+use crate::board::{Board, FenError, Piece, Piece::*};
 use crate::gen_table::{LocStack, Tables, new_tables, print_board};
-use crate::movegen::{Move::*, Move, Moves};
+use crate::movegen::{Move::*, Move, Moves, Undo};
+use crate::zobrist;
+
+pub const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+// stands in the history for the position before a null move, so that no
+// repetition is counted across it
+const NULL_MOVE: u64 = 0;
 
 const LIGHT_SQUARES: u64 = 0x55AA55AA55AA55AA;
 const DARK_SQUARES : u64 = 0xAA55AA55AA55AA55;
 
+pub struct PositionUndo {
+    pub board: Undo,
+    pub fifty: usize,
+    pub full_moves: usize,
+    pub threats: u64,
+    pub moves: Moves,
+}
+
 #[derive(Clone)]
 pub struct Position<'a> {
     pub board: Board,
@@ -12,7 +27,8 @@ pub struct Position<'a> {
     pub full_moves: usize,
     pub tables: &'a Tables,
     pub threats: u64,
-    pub moves: Moves
+    pub moves: Moves,
+    pub history: Vec<u64>,
 }
 
 impl Position<'_> {
@@ -23,7 +39,8 @@ impl Position<'_> {
             full_moves: 1,
             tables,
             threats: 0,
-            moves: Moves::new()
+            moves: Moves::new(),
+            history: Vec::new(),
         }
     }
 
@@ -51,7 +68,9 @@ impl Position<'_> {
                 if let Some(mut n) = ch.to_digit(18) {
                     if n >= 10 {
                         n -= 10;
-                        out.board.pawn |= 1 << (n + 56);
+                        // black's code goes on rank 1 so it ends up on rank 8
+                        // when the board is inverted below
+                        out.board.pawn |= 1 << (n + if player == 'b' {0} else {56});
                     }
                 }
             }
@@ -60,6 +79,8 @@ impl Position<'_> {
         out.fifty      = words.next().unwrap().to_string().parse().unwrap();
         out.full_moves = words.next().unwrap().to_string().parse().unwrap();
 
+        out.board.key = out.board.gen_key();
+
         if player == 'b' {
             out.board.invert();
         }
@@ -67,25 +88,157 @@ impl Position<'_> {
         out
     }
 
+    pub fn try_from_fen<'a>(tables: &'a Tables, fen: &str) -> Result<Position<'a>, FenError> {
+        let words: Vec<&str> = fen.split_whitespace().collect();
+
+        if words.len() < 4 {
+            return Err(FenError::MissingField);
+        }
+
+        let board = Board::try_from_fen(words[0])?;
+
+        let white = match words[1] {
+            "w" => true,
+            "b" => false,
+            _ => return Err(FenError::Player),
+        };
+
+        if words[2] != "-" {
+            let mut seen = String::new();
+            for c in words[2].chars() {
+                if !"KQkq".contains(c) || seen.contains(c) {
+                    return Err(FenError::Castling);
+                }
+                seen.push(c);
+            }
+        }
+
+        if words[3] != "-" {
+            let mut chars = words[3].chars();
+            let file = chars.next().and_then(|c| c.to_digit(18)).filter(|n| *n >= 10);
+            let rank = chars.next();
+
+            // the pawn which just moved two squares must be in front of the
+            // square, with the square itself and the one behind it empty
+            let (pawn, other, behind, empty) =
+                match (file, rank, chars.next(), white) {
+                    (Some(f), Some('6'), None, true ) => (32 + f - 10, board.other, 48 + f - 10, 40 + f - 10),
+                    (Some(f), Some('3'), None, false) => (24 + f - 10, board.curr ,  8 + f - 10, 16 + f - 10),
+                    _ => return Err(FenError::EnPassant),
+                };
+
+            if board.pawns() & other & (1 << pawn) == 0 ||
+               board.all() & (1 << behind | 1 << empty) != 0
+            {
+                return Err(FenError::EnPassant);
+            }
+        }
+
+        let fifty: usize =
+            match words.get(4) {
+                Some(w) => w.parse().map_err(|_| FenError::Fifty)?,
+                None => 0,
+            };
+        let full_moves: usize =
+            match words.get(5) {
+                Some(w) => w.parse().map_err(|_| FenError::FullMoves)?,
+                None => 1,
+            };
+
+        if full_moves == 0 {
+            return Err(FenError::FullMoves);
+        }
+        if words.len() > 6 {
+            return Err(FenError::ExtraFields);
+        }
+
+        for (c, king, rook, color) in [
+            ('K', 4 , 7 , board.curr ),
+            ('Q', 4 , 0 , board.curr ),
+            ('k', 60, 63, board.other),
+            ('q', 60, 56, board.other),
+        ].iter() {
+            let king_loc = if *king < 8 {board.cking} else {board.oking};
+
+            if words[2].contains(*c) &&
+               (king_loc != *king || board.rook & !board.bishop & color & (1 << rook) == 0)
+            {
+                return Err(FenError::CastlingRights);
+            }
+        }
+
+        let out = Position::from_fen(tables, &format!("{} {} {} {} {} {}",
+            words[0], words[1], words[2], words[3], fifty, full_moves));
+
+        let mut board = out.board.clone();
+        board.invert();
+        if board.threats(tables) & (1 << board.cking) != 0 {
+            return Err(FenError::OpponentInCheck);
+        }
+
+        Ok(out)
+    }
+
+    pub fn to_fen(&self) -> String {
+        let (white, black) =
+            if self.board.inverted {
+                (self.board.castle_other, self.board.castle_curr)
+            } else {
+                (self.board.castle_curr, self.board.castle_other)
+            };
+
+        let mut castle = String::new();
+        for (c, ch) in [white[1], white[0], black[1], black[0]].iter().zip("KQkq".chars()) {
+            if *c {
+                castle.push(ch);
+            }
+        }
+        if castle.is_empty() {
+            castle.push('-');
+        }
+
+        let ep =
+            if self.board.pawn >> 56 != 0 {
+                let file = (self.board.pawn >> 56).trailing_zeros() as u8;
+                let rank = if self.board.inverted {'3'} else {'6'};
+                format!("{}{}", (b'a' + file) as char, rank)
+            } else {
+                "-".to_string()
+            };
+
+        format!("{} {} {} {} {} {}",
+                self.board.to_fen(),
+                if self.board.inverted {'b'} else {'w'},
+                castle,
+                ep,
+                self.fifty,
+                self.full_moves)
+    }
+
     pub fn gen_moves(&self) -> Vec<Move> {
-        let mut board;
+        let mut board = self.board.clone();
         let mut threat;
         let mut out = Vec::new();
 
         for m in self.moves.clone() {
-            board = self.board.clone();
-            board.do_move(&m);
+            let undo = board.do_move(&m);
             threat = board.threats(&self.tables);
 
             if threat & (1 << board.cking) == 0 {
-                out.push(m);
+                out.push(m.clone());
             }
+            board.unmake_move(&m, &undo);
         }
 
         out
     }
 
-    pub fn do_move(&mut self, m: &Move) {
+    pub fn do_move(&mut self, m: &Move) -> PositionUndo {
+        let fifty = self.fifty;
+        let full_moves = self.full_moves;
+        let threats = self.threats;
+        let moves = std::mem::replace(&mut self.moves, Moves::new());
+
         self.threats = 0;
         self.fifty += 1;
 
@@ -105,7 +258,78 @@ impl Position<'_> {
         if self.board.inverted {
             self.full_moves += 1;
         }
-        self.board.do_move(m);
+        self.history.push(self.board.key);
+
+        PositionUndo {
+            board: self.board.do_move(m),
+            fifty,
+            full_moves,
+            threats,
+            moves,
+        }
+    }
+
+    // takes back m, which must be the last move done and already be
+    // inverted back to the player who made it
+    pub fn unmake_move(&mut self, m: &Move, undo: PositionUndo) {
+        self.board.unmake_move(m, &undo.board);
+        self.fifty = undo.fifty;
+        self.full_moves = undo.full_moves;
+        self.threats = undo.threats;
+        self.moves = undo.moves;
+        self.history.pop();
+    }
+
+    // passes the turn, which only loses the right to take en passant; the
+    // board still has to be inverted
+    pub fn do_null_move(&mut self) -> PositionUndo {
+        let undo = PositionUndo {
+            board: Undo {
+                captured: Empty,
+                castle_curr: self.board.castle_curr,
+                castle_other: self.board.castle_other,
+                ep: self.board.pawn & 0xff000000000000ff,
+                key: self.board.key,
+            },
+            fifty: self.fifty,
+            full_moves: self.full_moves,
+            threats: self.threats,
+            moves: std::mem::replace(&mut self.moves, Moves::new()),
+        };
+
+        self.threats = 0;
+        self.fifty += 1;
+        if self.board.inverted {
+            self.full_moves += 1;
+        }
+        self.history.push(NULL_MOVE);
+
+        self.board.key ^= zobrist::ep_key(self.board.pawn);
+        self.board.pawn &= 0x00ffffffffffffff;
+        undo
+    }
+
+    pub fn unmake_null_move(&mut self, undo: PositionUndo) {
+        self.board.pawn = self.board.pawn & 0x00ffffffffffff00 | undo.board.ep;
+        self.board.key = undo.board.key;
+        self.fifty = undo.fifty;
+        self.full_moves = undo.full_moves;
+        self.threats = undo.threats;
+        self.moves = undo.moves;
+        self.history.pop();
+    }
+
+    // number of earlier occurrences of the current position since the last
+    // irreversible move or null move
+    pub fn repetitions(&self) -> usize {
+        self.history.iter()
+            .rev()
+            .take(self.fifty)
+            .take_while(|k| **k != NULL_MOVE)
+            .skip(1)
+            .step_by(2)
+            .filter(|k| **k == self.board.key)
+            .count()
     }
 
     pub fn set_threats(&mut self) {
@@ -156,7 +380,7 @@ impl Position<'_> {
 
             block_squares |= self.tables.knight[loc] & self.board.other & self.board.knight();
 
-            block_squares |= self.tables.other_pawn_takes[loc] & self.board.other & self.board.pawns();
+            block_squares |= self.tables.pawn_takes[loc] & self.board.other & self.board.pawns();
 
             let king_moves = self.moves.bits.last().unwrap().clone();
             for m in self.moves.bits.iter_mut() {
@@ -179,7 +403,7 @@ impl Position<'_> {
     pub fn test_endgame(&mut self) -> Option<usize> {
         self.set_threats();
 
-        if self.fifty >= 50 {
+        if self.fifty >= 50 || self.repetitions() >= 2 {
             return Some(1)
         }
 
@@ -207,13 +431,13 @@ impl Position<'_> {
         let mut board = self.board.clone();
 
         for m in self.moves.clone() {
-            board.do_move(&m);
+            let undo = board.do_move(&m);
             threat = board.threats(&self.tables);
 
             if threat & (1 << board.cking) == 0 {
                 return None;
             }
-            board = self.board.clone();
+            board.unmake_move(&m, &undo);
         }
 
         if self.is_in_check() {
@@ -229,26 +453,28 @@ impl Position<'_> {
             self.set_moves();
             self.set_threats();
 
+            if self.repetitions() > 0 {
+                return 1;
+            }
+
             match self.test_endgame() {
                 None => {},
                 Some(x) => return x,
             }
 
-            let mut board = self.board.clone();
-            let mut mov = self.do_random_move();
+            let mut mov = self.board.get_random_move(&self.moves);
 
-            self.threats = 0;
-            self.set_threats();
+            loop {
+                let mut board = self.board.clone();
+                board.do_move(&mov);
 
-            while self.is_in_check() {
-                self.board = board.clone();
-                mov = self.do_random_move();
-                if self.fifty != 0 {
-                    self.fifty -= 1;
+                if board.threats(&self.tables) & (1 << board.cking) == 0 {
+                    break;
                 }
-                self.threats = 0;
-                self.set_threats();
+                mov = self.board.get_random_move(&self.moves);
             }
+
+            self.do_move(&mov);
             self.board.invert();
         }
     }
@@ -0,0 +1,199 @@
+use crate::board::{Board, Piece, Piece::*};
+use crate::movegen::{Moves, Move, Move::*};
+use crate::position::Position;
+
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SanError {
+    Invalid,
+    Illegal,
+    Ambiguous,
+}
+
+impl fmt::Display for SanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}",
+            match self {
+                SanError::Invalid   => "not a move in algebraic notation",
+                SanError::Illegal   => "illegal move",
+                SanError::Ambiguous => "ambiguous move",
+            })
+    }
+}
+
+impl std::error::Error for SanError {}
+
+// source and destination of a move relative to the player to move
+pub fn move_squares(m: &Move) -> (u8, u8) {
+    match m {
+        Basic(f, t) => (*f, *t),
+        En_passant(f, t) => (f + 32, t + 40),
+        Castle_queen => (4, 2),
+        Castle_king => (4, 6),
+        Promotion(_, f, t) => (*f, *t),
+    }
+}
+
+fn piece_char(piece: Piece) -> &'static str {
+    match piece {
+        Knight => "N",
+        Bishop => "B",
+        Rook   => "R",
+        Queen  => "Q",
+        King   => "K",
+        _      => "",
+    }
+}
+
+fn char_piece(c: char) -> Option<Piece> {
+    match c {
+        'N' => Some(Knight),
+        'B' => Some(Bishop),
+        'R' => Some(Rook),
+        'Q' => Some(Queen),
+        'K' => Some(King),
+        _   => None,
+    }
+}
+
+fn moved_piece(board: &Board, m: &Move) -> Piece {
+    match m {
+        En_passant(_, _) | Promotion(_, _, _) => Pawn,
+        Castle_king | Castle_queen => King,
+        Basic(from, _) => board.get_loc_piece(*from),
+    }
+}
+
+fn legal_moves<'a>(pos: &Position<'a>) -> (Position<'a>, Vec<Move>) {
+    let mut pos = pos.clone();
+    pos.set_moves();
+    let moves = pos.gen_moves();
+    (pos, moves)
+}
+
+pub fn move_to_san(pos: &Position, m: &Move) -> String {
+    let (mut pos, moves) = legal_moves(pos);
+    let board = &pos.board;
+    let mut out = String::new();
+
+    match m {
+        Castle_king  => out.push_str("O-O"),
+        Castle_queen => out.push_str("O-O-O"),
+        _ => {
+            let piece = moved_piece(board, m);
+            let (from, to) = move_squares(m);
+            let from_str = Moves::loc_to_string(from, board.inverted);
+            let capture = board.other & (1 << to) != 0 || from % 8 != to % 8 && piece == Pawn;
+
+            out.push_str(piece_char(piece));
+
+            if piece == Pawn {
+                if capture {
+                    out.push_str(&from_str[..1]);
+                }
+            } else {
+                let others: Vec<u8> = moves.iter()
+                    .filter(|o| moved_piece(board, o) == piece && move_squares(o).1 == to)
+                    .map(|o| move_squares(o).0)
+                    .filter(|f| *f != from)
+                    .collect();
+
+                if !others.is_empty() {
+                    if others.iter().all(|f| f % 8 != from % 8) {
+                        out.push_str(&from_str[..1]);
+                    } else if others.iter().all(|f| f / 8 != from / 8) {
+                        out.push_str(&from_str[1..]);
+                    } else {
+                        out.push_str(&from_str);
+                    }
+                }
+            }
+
+            if capture {
+                out.push('x');
+            }
+            out.push_str(&Moves::loc_to_string(to, board.inverted));
+
+            if let Promotion(p, _, _) = m {
+                out.push('=');
+                out.push_str(piece_char(*p));
+            }
+        }
+    }
+
+    pos.do_move(m);
+    pos.board.invert();
+    pos.set_moves();
+
+    if pos.is_in_check() {
+        out.push(if pos.gen_moves().is_empty() {'#'} else {'+'});
+    }
+
+    out
+}
+
+pub fn san_to_move(pos: &Position, s: &str) -> Result<Move, SanError> {
+    let (pos, moves) = legal_moves(pos);
+    let board = &pos.board;
+    let s = s.trim_end_matches(|c| "+#!?".contains(c));
+
+    let candidates: Vec<Move> =
+        match s {
+            "O-O" | "0-0" => moves.into_iter().filter(|m| *m == Castle_king).collect(),
+            "O-O-O" | "0-0-0" => moves.into_iter().filter(|m| *m == Castle_queen).collect(),
+            _ => {
+                let mut chars: Vec<char> = s.chars().filter(|c| *c != 'x' && *c != '=').collect();
+
+                let piece =
+                    match chars.first().and_then(|c| char_piece(*c)) {
+                        Some(p) => {
+                            chars.remove(0);
+                            p
+                        },
+                        None => Pawn,
+                    };
+
+                let promotion =
+                    match chars.last().and_then(|c| char_piece(c.to_ascii_uppercase())) {
+                        Some(p) if piece == Pawn && chars.len() > 2 => {
+                            chars.pop();
+                            Some(p)
+                        },
+                        _ => None,
+                    };
+
+                if chars.len() < 2 || chars.len() > 4 {
+                    return Err(SanError::Invalid);
+                }
+
+                let dest: String = chars[chars.len() - 2..].iter().collect();
+                let hint = &chars[..chars.len() - 2];
+
+                if !dest.starts_with(|c| ('a'..='h').contains(&c)) ||
+                   !dest.ends_with(|c| ('1'..='8').contains(&c)) ||
+                   !hint.iter().all(|c| ('a'..='h').contains(c) || ('1'..='8').contains(c))
+                {
+                    return Err(SanError::Invalid);
+                }
+
+                moves.into_iter().filter(|m| {
+                    let (from, to) = move_squares(m);
+                    let from_str = Moves::loc_to_string(from, board.inverted);
+                    let promoted = if let Promotion(p, _, _) = m {Some(*p)} else {None};
+
+                    moved_piece(board, m) == piece &&
+                        Moves::loc_to_string(to, board.inverted) == dest &&
+                        promoted == promotion &&
+                        hint.iter().all(|c| from_str.contains(*c)) &&
+                        *m != Castle_king && *m != Castle_queen
+                }).collect()
+            }
+        };
+
+    match candidates.len() {
+        0 => Err(SanError::Illegal),
+        1 => Ok(candidates[0].clone()),
+        _ => Err(SanError::Ambiguous),
+    }
+}
@@ -1,6 +1,6 @@
 use crate::position::*;
 use crate::movegen::{Moves, Move, Move::*};
-use crate::board::Board;
+use crate::board::{Board, Piece::*};
 
 use std::cell::RefCell;
 use std::rc::Rc;
@@ -58,27 +58,31 @@ impl GameTree {
 
     fn expand(&mut self, position: &mut Position) {
         position.set_moves();
+        let black = position.board.inverted;
         self.children = position.gen_moves().into_iter().map(|m|
         {
-            let mut pos = position.clone();
-            pos.do_move(&m);
-            pos.board.invert();
+            let undo = position.do_move(&m);
+            position.board.invert();
             let mut child = GameTree::new();
-            child.black = position.board.inverted;
+            child.black = black;
             child.visits = 1;
-            child.mov = m;
 
-            pos.set_moves();
-            if let Some(n) = pos.test_endgame() {
+            position.set_moves();
+            if let Some(n) = position.test_endgame() {
                 child.endgame = true;
                 child.score = n as f64 / 2.;
             } else {
-                child.score = pos.do_rollout() as f64 / 2.;
+                // the rollout plays the game out, so it needs its own copy
+                child.score = position.clone().do_rollout() as f64 / 2.;
             }
 
-            if !pos.board.inverted {
+            if !position.board.inverted {
                 child.score = 1. - child.score;
             }
+            position.board.invert();
+            position.unmake_move(&m, undo);
+
+            child.mov = m;
             Rc::new(RefCell::new(child))
         }).collect();
     }
@@ -198,88 +202,780 @@ impl GameTree {
     }
 }
 
-use crate::eval::eval;
+use crate::eval::{eval, piece_value};
+use crate::ordering::{MoveOrder, mvv_lva};
+use crate::tt::{TTable, Bound, MATE_BOUND, score_from_tt};
+use crate::syzygy::{Probe, can_probe, root_move};
 
-pub fn alphabeta(mut pos: Position,
-                 mut alpha: i32,
-                 beta: i32,
-                 depth: usize) -> i32
-{
-    pos.set_moves();
-    if let Some(n) = pos.test_endgame() {
-        if n == 1 {
-            return 0;
-        } else {
-            return -100000;
-        }
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::SystemTime;
+
+pub const MATE: i32 = 100000;
+
+// deepest iteration helper threads search when no depth limit is given
+const MAX_DEPTH: usize = 100;
+
+// quiescence plies in which all evasions are searched when in check; past
+// them only captures and promotions are
+pub const QUIESCE_EVASIONS: usize = 4;
+
+pub fn is_tactical(board: &Board, m: &Move) -> bool {
+    match m {
+        Basic(_, to) => board.other & (1 << to) != 0,
+        En_passant(_, _) | Promotion(_, _, _) => true,
+        _ => false,
+    }
+}
+
+pub fn captured_value(board: &Board, m: &Move) -> i32 {
+    match m {
+        Basic(_, to) => piece_value(board.get_loc_piece(*to)),
+        En_passant(_, _) => piece_value(Pawn),
+        Promotion(p, _, to) => piece_value(*p) + piece_value(board.get_loc_piece(*to)),
+        _ => 0,
     }
+}
 
-    if depth == 0 {return eval(&mut pos)}
+#[derive(Clone, Debug, Default)]
+pub struct SearchLimits {
+    // no iteration is started after this many milliseconds
+    pub time: Option<u128>,
+    // the search is aborted after this many milliseconds, time if not given
+    pub deadline: Option<u128>,
+    pub depth: Option<usize>,
+    pub nodes: Option<usize>,
+}
 
-    for m in pos.gen_moves() {
-        let mut p = pos.clone();
-        p.do_move(&m);
-        p.board.invert();
-        let score = -alphabeta(p, -beta, -alpha, depth - 1);
-        if score >= beta {
-            return beta;
+// what one iteration of the search found
+#[derive(Clone, Debug, PartialEq)]
+pub struct Info {
+    pub depth: usize,
+    pub seldepth: usize,
+    // rank of the line, from 1
+    pub multipv: usize,
+    pub score: i32,
+    pub nodes: usize,
+    // milliseconds since the search started
+    pub time: u128,
+    // in coordinate notation
+    pub pv: Vec<String>,
+}
+
+impl Info {
+    pub fn nps(&self) -> u128 {
+        self.nodes as u128 * 1000 / self.time.max(1)
+    }
+}
+
+// moves until mate for a mate score, negative when getting mated
+pub fn mate_in(score: i32) -> Option<i32> {
+    if score >= MATE_BOUND {
+        Some((MATE - score + 1) / 2)
+    } else if score <= -MATE_BOUND {
+        Some(-(MATE + score) / 2)
+    } else {
+        None
+    }
+}
+
+// in the format of a UCI info line
+impl fmt::Display for Info {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "depth {} seldepth {} multipv {} score ", self.depth, self.seldepth, self.multipv)?;
+        match mate_in(self.score) {
+            Some(n) => write!(f, "mate {}", n)?,
+            None => write!(f, "cp {}", self.score)?,
         }
-        if score > alpha {
-            alpha = score;
+        write!(f, " nodes {} nps {} time {} pv {}", self.nodes, self.nps(), self.time, self.pv.join(" "))
+    }
+}
+
+// bounds of a full window, beyond any score
+const INFINITY: i32 = 1000000;
+
+// half width of the first aspiration window
+const ASPIRATION_WINDOW: i32 = 25;
+
+// root searches made by the aspiration windows, and how many of them had to
+// be repeated with a wider window
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SearchStats {
+    pub searches: usize,
+    pub fail_highs: usize,
+    pub fail_lows: usize,
+}
+
+impl SearchStats {
+    pub fn researches(&self) -> usize {
+        self.fail_highs + self.fail_lows
+    }
+}
+
+// Parts of the search that can be turned off, to measure what each is worth
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchOptions {
+    // sort moves by MVV-LVA, killers and history rather than only trying the
+    // hash move first
+    pub order_moves: bool,
+    // principal variation search
+    pub pvs: bool,
+    pub null_move: bool,
+    // late move reductions
+    pub lmr: bool,
+    // search each iteration with a narrow window around the last score
+    pub aspiration: bool,
+}
+
+impl Default for SearchOptions {
+    fn default() -> SearchOptions {
+        SearchOptions {
+            order_moves: true,
+            pvs: true,
+            null_move: true,
+            lmr: true,
+            aspiration: true,
         }
     }
-    return alpha;
 }
 
-pub fn best_move(mut pos: Position, depth: usize, lastbest: Option<Move>)
-    -> (Option<Move>, i32)
-{
-    pos.set_moves();
-    let mut best_move = None;
-    let mut best_score = -1000000;
-    let mut moves = pos.gen_moves();
-    if lastbest != None {
-        let lastbest = lastbest.unwrap();
-        moves.retain(|m| *m != lastbest);
-        moves.push(lastbest);
-    }
-    for m in moves.into_iter().rev() {
-        let mut p = pos.clone();
-        p.do_move(&m);
-        p.board.invert();
-        let score = -alphabeta(p, -1000000, -best_score, depth - 1);
-        if score > best_score {
-            // println!("{:?} {}", m, score);
-            best_move = Some(m);
-            best_score = score;
-        }
-    }
-    return (best_move, best_score);
+// milliseconds kept on the clock for communication delays
+const OVERHEAD: u128 = 50;
+
+impl SearchLimits {
+    // Sets the time limits for a move with clock milliseconds left, inc
+    // added after the move and moves_to_go moves to the next time control,
+    // or about 30 for the rest of the game. An iteration may run up to three
+    // times past the target, but never past half of the time left unless
+    // this is the last move before the time control.
+    pub fn set_clock(&mut self, clock: u128, inc: u128, moves_to_go: Option<u128>) {
+        let max = clock.saturating_sub(OVERHEAD);
+        let target = (clock / moves_to_go.unwrap_or(30).max(1) + inc).min(max);
+
+        self.time = Some(target);
+        self.deadline = Some((target * 3).min(max / 2).max(target));
+    }
+}
+
+pub struct Search {
+    pub limits: SearchLimits,
+    pub stop: Arc<AtomicBool>,
+    pub nodes: usize,
+    pub depth: usize,
+    pub seldepth: usize,
+    pub score: i32,
+    // principal variation of the last completed iteration
+    pub pv: Vec<Move>,
+    // number of best lines searched, and those found in the last completed
+    // iteration with their scores, best first
+    pub multipv: usize,
+    pub lines: Vec<(Move, i32, Vec<Move>)>,
+    // called by the main thread after each completed iteration
+    pub on_info: Option<Box<dyn FnMut(&Info) + Send>>,
+    // triangular PV table: the line found from each ply of the current node
+    pv_table: Vec<Vec<Move>>,
+    pub tt: Arc<TTable>,
+    pub tb: Option<Arc<dyn Probe>>,
+    pub options: SearchOptions,
+    pub stats: SearchStats,
+    order: MoveOrder,
+    // set for the node right after a null move
+    after_null: bool,
+    // number of threads searching, sharing the table and stop flag
+    pub threads: usize,
+    // 0 for the main thread, which reports the result
+    id: usize,
+    start: SystemTime,
 }
 
-use std::io::Write;
-use std::time::{Duration, SystemTime};
+impl Search {
+    pub fn new(limits: SearchLimits) -> Search {
+        Search::with_tt(limits, Arc::new(TTable::new(16)))
+    }
 
-pub fn ab_search(mut pos: Position, time: usize) -> (Option<Move>, i32) {
-    let time = time as u128;
-    let now = SystemTime::now();
+    pub fn with_tt(limits: SearchLimits, tt: Arc<TTable>) -> Search {
+        Search {
+            limits,
+            tt,
+            stop: Arc::new(AtomicBool::new(false)),
+            nodes: 0,
+            depth: 0,
+            seldepth: 0,
+            score: 0,
+            pv: Vec::new(),
+            multipv: 1,
+            lines: Vec::new(),
+            on_info: None,
+            pv_table: Vec::new(),
+            tb: None,
+            options: SearchOptions::default(),
+            stats: SearchStats::default(),
+            order: MoveOrder::new(),
+            after_null: false,
+            threads: 1,
+            id: 0,
+            start: SystemTime::now(),
+        }
+    }
 
-    let mut best = None;
-    let mut score = 0;;
-    let mut d = 1;
+    // A helper thread of Lazy SMP. Helpers search the same position with the
+    // same table until the main thread stops them, differing from it only in
+    // the depths they search and their root move order, and what they find
+    // reaches the main thread through the table.
+    fn helper(&self, id: usize) -> Search {
+        Search {
+            limits: SearchLimits {
+                depth: Some(self.limits.depth.unwrap_or(MAX_DEPTH)),
+                ..SearchLimits::default()
+            },
+            stop: self.stop.clone(),
+            nodes: 0,
+            depth: 0,
+            seldepth: 0,
+            score: 0,
+            pv: Vec::new(),
+            multipv: 1,
+            lines: Vec::new(),
+            on_info: None,
+            pv_table: Vec::new(),
+            tt: self.tt.clone(),
+            tb: self.tb.clone(),
+            options: self.options.clone(),
+            stats: SearchStats::default(),
+            order: MoveOrder::new(),
+            after_null: false,
+            threads: 1,
+            id,
+            start: self.start,
+        }
+    }
+
+    pub fn elapsed(&self) -> u128 {
+        self.start.elapsed().map(|d| d.as_millis()).unwrap_or(0)
+    }
+
+    fn clear_pv(&mut self, ply: usize) {
+        if self.pv_table.len() <= ply + 1 {
+            self.pv_table.resize(ply + 2, Vec::new());
+        }
+        self.pv_table[ply].clear();
+    }
+
+    // m followed by the line found after it
+    fn update_pv(&mut self, ply: usize, m: &Move) {
+        let (line, rest) = self.pv_table.split_at_mut(ply + 1);
+        line[ply].clear();
+        line[ply].push(m.clone());
+        line[ply].extend_from_slice(&rest[0]);
+    }
+
+    fn report(&mut self, pos: &Position) {
+        let time = self.elapsed();
+
+        if let Some(f) = self.on_info.as_mut() {
+            for (k, (_, score, pv)) in self.lines.iter().enumerate() {
+                let mut inverted = !pos.board.inverted;
+
+                f(&Info {
+                    depth: self.depth,
+                    seldepth: self.seldepth,
+                    multipv: k + 1,
+                    score: *score,
+                    nodes: self.nodes,
+                    time,
+                    pv: pv.iter().map(|m| {
+                        inverted = !inverted;
+                        Moves::move_to_string(m, inverted)
+                    }).collect(),
+                });
+            }
+        }
+    }
+
+    fn stopped(&self) -> bool {
+        if let Some(n) = self.limits.nodes {
+            if self.nodes >= n {
+                self.stop.store(true, Ordering::Relaxed);
+            }
+        }
+        // the clock is only read every 1024 nodes
+        if self.nodes & 1023 == 0 {
+            if let Some(t) = self.limits.deadline.or(self.limits.time) {
+                if self.elapsed() >= t {
+                    self.stop.store(true, Ordering::Relaxed);
+                }
+            }
+        }
+        self.stop.load(Ordering::Relaxed)
+    }
+
+    pub fn alphabeta(&mut self,
+                     pos: &mut Position,
+                     mut alpha: i32,
+                     beta: i32,
+                     depth: usize,
+                     ply: usize) -> i32
+    {
+        let after_null = std::mem::replace(&mut self.after_null, false);
+
+        self.clear_pv(ply);
+        self.seldepth = self.seldepth.max(ply);
+        self.nodes += 1;
+        if self.stopped() {
+            return 0;
+        }
+
+        if ply > 0 && pos.repetitions() > 0 {
+            return 0;
+        }
+
+        // mates are scored by their distance from the root, so that faster
+        // ones are preferred
+        pos.set_moves();
+        if let Some(n) = pos.test_endgame() {
+            if n == 1 {
+                return 0;
+            } else {
+                return -MATE + ply as i32;
+            }
+        }
+
+        // no line from here can beat a mate found closer to the root
+        let beta = beta.min(MATE - ply as i32 - 1);
+        alpha = alpha.max(-MATE + ply as i32);
+        if alpha >= beta {
+            return alpha;
+        }
+
+        // tables are only probed right after a capture or pawn move, as they
+        // don't know how much of the fifty move rule is used up
+        if let Some(tb) = &self.tb {
+            if ply > 0 && pos.fifty == 0 && can_probe(tb.as_ref(), pos) {
+                if let Some(wdl) = tb.probe_wdl(pos) {
+                    return wdl.score(ply).max(alpha).min(beta);
+                }
+            }
+        }
+
+        if depth == 0 {return self.quiesce(pos, alpha, beta, ply, 0)}
+
+        let key = pos.board.key;
+        let mut tt_move = None;
+
+        if let Some(entry) = self.tt.probe(key) {
+            tt_move = entry.mov.clone();
+
+            if entry.depth as usize >= depth {
+                let score = score_from_tt(entry.score, ply);
+
+                match entry.bound {
+                    Bound::Exact => return score.max(alpha).min(beta),
+                    Bound::Lower if score >= beta => return beta,
+                    Bound::Upper if score <= alpha => return alpha,
+                    _ => {}
+                }
+            }
+        }
+
+        let in_check = pos.is_in_check();
+
+        // If passing still leaves the opponent failing high after a reduced
+        // search, a real move almost surely will too. Not tried twice in a
+        // row, in check, or with only pawns, where zugzwang is common.
+        if self.options.null_move && !after_null && !in_check && ply > 0 && depth >= 3 && beta < MATE_BOUND &&
+           pos.board.curr & !pos.board.pawns() & !(1 << pos.board.cking) != 0 &&
+           eval(pos) >= beta
+        {
+            let r = if depth > 6 {3} else {2};
+
+            let undo = pos.do_null_move();
+            pos.board.invert();
+            self.after_null = true;
+            let score = -self.alphabeta(pos, -beta, -beta + 1, depth - 1 - r, ply + 1);
+            pos.board.invert();
+            pos.unmake_null_move(undo);
 
-    while now.elapsed().ok().unwrap().as_millis() < time {
-        print!("{}", d % 10);
-        std::io::stdout().flush();
-        match best_move(pos.clone(), d, best) {
-            (None, _) => return (None, score),
-            (m, s) => {
-                best = m;
-                score = s
+            if self.stopped() {
+                return 0;
             }
+            if score >= beta {
+                return beta;
+            }
+        }
+
+        let mut moves = pos.gen_moves();
+        if self.options.order_moves {
+            self.order.sort(&pos.board, &mut moves, tt_move.as_ref(), ply);
+        } else if let Some(m) = &tt_move {
+            if let Some(i) = moves.iter().position(|x| x == m) {
+                moves.swap(0, i);
+            }
+        }
+
+        let mut best = None;
+
+        for (i, m) in moves.into_iter().enumerate() {
+            // quiet moves late in the order are searched a ply or two less
+            let reduction =
+                if self.options.lmr && i >= 3 && depth >= 3 && !in_check && !is_tactical(&pos.board, &m) {
+                    if i >= 6 {2} else {1}
+                } else {
+                    0
+                };
+
+            let undo = pos.do_move(&m);
+            pos.board.invert();
+
+            // After the first move, the others only have to be shown no better
+            // than alpha with a null window, or a reduced depth, and are
+            // searched again in full if they are not.
+            let reduced = i > 0 && (self.options.pvs || reduction > 0);
+            let mut score = 0;
+
+            if reduced {
+                let a = if self.options.pvs {-alpha - 1} else {-beta};
+                score = -self.alphabeta(pos, a, -alpha, depth - 1 - reduction, ply + 1);
+            }
+            if !reduced || score > alpha && (reduction > 0 || beta - alpha > 1) {
+                score = -self.alphabeta(pos, -beta, -alpha, depth - 1, ply + 1);
+            }
+
+            pos.board.invert();
+            pos.unmake_move(&m, undo);
+
+            if self.stopped() {
+                return 0;
+            }
+            if score >= beta {
+                if self.options.order_moves {
+                    self.order.cutoff(&pos.board, &m, depth, ply);
+                }
+                self.tt.store(key, depth, Bound::Lower, beta, Some(m), ply);
+                return beta;
+            }
+            if score > alpha {
+                alpha = score;
+                self.update_pv(ply, &m);
+                best = Some(m);
+            }
+        }
+
+        let bound = if best.is_some() {Bound::Exact} else {Bound::Upper};
+        self.tt.store(key, depth, bound, alpha, best.or(tt_move), ply);
+        return alpha;
+    }
+
+    // Searches captures and promotions until the position is quiet, or all
+    // evasions when in check. Expects the moves of pos to already be set.
+    pub fn quiesce(&mut self,
+                   pos: &mut Position,
+                   mut alpha: i32,
+                   beta: i32,
+                   ply: usize,
+                   qply: usize) -> i32
+    {
+        self.clear_pv(ply);
+        self.seldepth = self.seldepth.max(ply);
+        self.nodes += 1;
+        if self.stopped() {
+            return 0;
+        }
+
+        let in_check = pos.is_in_check();
+        let evade = in_check && qply < QUIESCE_EVASIONS;
+
+        if !evade {
+            let stand_pat = eval(pos);
+            if stand_pat >= beta {
+                return beta;
+            }
+            if stand_pat > alpha {
+                alpha = stand_pat;
+            }
+        }
+
+        let mut moves = pos.gen_moves();
+
+        if moves.is_empty() {
+            return if in_check {-MATE + ply as i32} else {0};
+        }
+
+        if !evade {
+            moves.retain(|m| is_tactical(&pos.board, m));
+            if self.options.order_moves {
+                moves.sort_by_key(|m| -mvv_lva(&pos.board, m));
+            } else {
+                moves.sort_by_key(|m| -captured_value(&pos.board, m));
+            }
+        }
+
+        for m in moves {
+            let undo = pos.do_move(&m);
+            pos.board.invert();
+            pos.set_moves();
+            let score = -self.quiesce(pos, -beta, -alpha, ply + 1, qply + 1);
+            pos.board.invert();
+            pos.unmake_move(&m, undo);
+
+            if score >= beta {
+                return beta;
+            }
+            if score > alpha {
+                alpha = score;
+            }
+        }
+        alpha
+    }
+
+    #[cfg(test)]
+    pub fn best_move(&mut self, pos: Position, depth: usize, lastbest: Option<Move>)
+        -> (Option<Move>, i32)
+    {
+        self.root_search(pos, depth, lastbest, &[], -INFINITY, INFINITY)
+    }
+
+    // The best move other than the excluded ones, which are lines already
+    // found in MultiPV mode. No move is returned if none scores above alpha,
+    // and the search stops at the first move scoring beta or more.
+    fn root_search(&mut self,
+                   mut pos: Position,
+                   depth: usize,
+                   lastbest: Option<Move>,
+                   excluded: &[Move],
+                   alpha: i32,
+                   beta: i32) -> (Option<Move>, i32)
+    {
+        pos.set_moves();
+        self.clear_pv(0);
+        let mut best_move = None;
+        let mut best_score = alpha;
+        let mut moves = pos.gen_moves();
+        moves.retain(|m| !excluded.contains(m));
+        if self.options.order_moves {
+            // moves are searched from the back
+            self.order.sort(&pos.board, &mut moves, None, 0);
+            moves.reverse();
+        }
+        if self.id > 0 && !moves.is_empty() {
+            let n = self.id % moves.len();
+            moves.rotate_left(n);
+        }
+        // the last best move may have gone to an earlier line since
+        if let Some(lastbest) = lastbest.filter(|m| !excluded.contains(m)) {
+            moves.retain(|m| *m != lastbest);
+            moves.push(lastbest);
+        }
+        for m in moves.into_iter().rev() {
+            let undo = pos.do_move(&m);
+            pos.board.invert();
+            let score = -self.alphabeta(&mut pos, -beta, -best_score, depth - 1, 1);
+            pos.board.invert();
+            pos.unmake_move(&m, undo);
+            if score > best_score {
+                self.update_pv(0, &m);
+                best_move = Some(m);
+                best_score = score;
+                if score >= beta {
+                    break;
+                }
+            }
+        }
+        if !self.stopped() && excluded.is_empty() && best_move.is_some() {
+            let bound = if best_score >= beta {Bound::Lower} else {Bound::Exact};
+            self.tt.store(pos.board.key, depth, bound, best_score, best_move.clone(), 0);
+        }
+        return (best_move, best_score);
+    }
+
+    // Searches with self.threads threads until one of the limits is hit. The
+    // result is that of the main thread; helpers only add their node counts.
+    pub fn run(&mut self, pos: Position) -> (Option<Move>, i32) {
+        self.start = SystemTime::now();
+        self.nodes = 0;
+        self.depth = 0;
+        self.stats = SearchStats::default();
+        self.order.new_search();
+
+        self.lines.clear();
+
+        if let Some((m, wdl)) = self.tb.as_ref().and_then(|tb| root_move(tb.as_ref(), &pos)) {
+            self.score = wdl.score(0);
+            self.pv = vec![m.clone()];
+            self.lines = vec![(m.clone(), self.score, self.pv.clone())];
+            return (Some(m), self.score);
+        }
+
+        if self.threads <= 1 {
+            return self.deepen(pos);
+        }
+
+        let helpers: Vec<Search> = (1..self.threads).map(|id| self.helper(id)).collect();
+
+        std::thread::scope(|s| {
+            let handles: Vec<_> = helpers.into_iter()
+                .map(|mut h| {
+                    let pos = pos.clone();
+                    s.spawn(move || {
+                        h.deepen(pos);
+                        h.nodes
+                    })
+                })
+                .collect();
+
+            let out = self.deepen(pos.clone());
+            self.stop.store(true, Ordering::Relaxed);
+
+            for h in handles {
+                self.nodes += h.join().unwrap();
+            }
+            out
+        })
+    }
+
+    // Searches a window around the score of the last iteration, which is
+    // widened on the side the score falls outside of until it lands inside.
+    fn aspiration(&mut self,
+                  pos: &Position,
+                  depth: usize,
+                  lastbest: Option<Move>,
+                  excluded: &[Move],
+                  guess: Option<i32>) -> (Option<Move>, i32)
+    {
+        let mut delta = ASPIRATION_WINDOW;
+        let (mut alpha, mut beta) =
+            match guess {
+                Some(g) if self.options.aspiration && depth >= 4 && g.abs() < MATE_BOUND => (g - delta, g + delta),
+                _ => (-INFINITY, INFINITY),
+            };
+
+        loop {
+            self.stats.searches += 1;
+            let (m, s) = self.root_search(pos.clone(), depth, lastbest.clone(), excluded, alpha, beta);
+
+            if self.stopped() {
+                return (m, s);
+            }
+
+            delta *= 4;
+            if s <= alpha && alpha > -INFINITY {
+                self.stats.fail_lows += 1;
+                alpha = if delta > 1000 {-INFINITY} else {alpha - delta};
+            } else if s >= beta && beta < INFINITY {
+                self.stats.fail_highs += 1;
+                beta = if delta > 1000 {INFINITY} else {beta + delta};
+            } else {
+                return (m, s);
+            }
+        }
+    }
+
+    // the best n moves in pos with their scores and lines, best first
+    pub fn top_moves(&mut self, pos: Position, n: usize) -> Vec<(Move, i32, Vec<Move>)> {
+        self.multipv = n;
+        self.run(pos);
+        self.lines.clone()
+    }
+
+    // Iterative deepening until one of the limits is hit. The result of an
+    // iteration interrupted by a stop is thrown away. Odd helpers start one
+    // iteration deeper than the others.
+    fn deepen(&mut self, pos: Position) -> (Option<Move>, i32) {
+        let mut d = 1 + self.id % 2;
+
+        loop {
+            self.seldepth = 0;
+            let mut lines: Vec<(Move, i32, Vec<Move>)> = Vec::new();
+
+            // each line is the best move left after those of the lines before
+            for k in 0..self.multipv.max(1) {
+                let excluded: Vec<Move> = lines.iter().map(|l| l.0.clone()).collect();
+                let lastbest = self.lines.get(k).map(|l| l.0.clone());
+
+                let guess = self.lines.get(k).map(|l| l.1);
+
+                match self.aspiration(&pos, d, lastbest, &excluded, guess) {
+                    (None, s) if k == 0 && !self.stopped() => return (None, s),
+                    (None, _) => break,
+                    (Some(m), s) => lines.push((m, s, self.pv_table[0].clone())),
+                }
+                if self.stopped() {
+                    break;
+                }
+            }
+
+            if self.stopped() && !self.lines.is_empty() || lines.is_empty() {
+                break;
+            }
+            lines.sort_by_key(|l| -l.1);
+            self.depth = d;
+            self.score = lines[0].1;
+            self.pv = lines[0].2.clone();
+            self.lines = lines;
+            self.report(&pos);
+
+            if self.stopped() ||
+               self.limits.depth.map_or(false, |max| d >= max) ||
+               self.limits.time.map_or(false, |t| self.elapsed() >= t)
+            {
+                break;
+            }
+            d += 1;
+        }
+        (self.lines.first().map(|l| l.0.clone()), self.score)
+    }
+}
+
+// searches pos for time milliseconds, printing what each iteration found
+pub fn ab_search(pos: Position, time: usize) -> (Option<Move>, i32) {
+    let mut search = Search::new(SearchLimits {
+        time: Some(time as u128),
+        ..SearchLimits::default()
+    });
+    search.on_info = Some(Box::new(|info| println!("{}", info)));
+    search.run(pos)
+}
+
+// Whether the player to move can mate in at most n moves whatever the
+// defence, and the first move of such a mate. Every move is tried without
+// pruning, so None proves there is no such mate.
+pub fn find_mate(pos: &Position, n: usize) -> Option<Move> {
+    let mut pos = pos.clone();
+    pos.set_moves();
+    mates(&mut pos, n)
+}
+
+fn mates(pos: &mut Position, n: usize) -> Option<Move> {
+    if n == 0 {
+        return None;
+    }
+
+    for m in pos.gen_moves() {
+        let undo = pos.do_move(&m);
+        pos.board.invert();
+        pos.set_moves();
+
+        let mate =
+            match pos.test_endgame() {
+                Some(1) => false,
+                Some(_) => true,
+                None => n > 1 && pos.gen_moves().into_iter().all(|r| {
+                    let undo = pos.do_move(&r);
+                    pos.board.invert();
+                    pos.set_moves();
+                    let mate = pos.test_endgame().is_none() && mates(pos, n - 1).is_some();
+                    pos.board.invert();
+                    pos.unmake_move(&r, undo);
+                    mate
+                }),
+            };
+
+        pos.board.invert();
+        pos.unmake_move(&m, undo);
+
+        if mate {
+            return Some(m);
         }
-        d += 1;
     }
-    println!();
-    (best, score)
+    None
 }
@@ -0,0 +1,398 @@
+use crate::epd::Epd;
+use crate::external::External;
+use crate::gen_table::{Tables, new_tables};
+use crate::movegen::Moves;
+use crate::pgn::{Game, result_string};
+use crate::position::{Position, START_FEN};
+use crate::search::{Search, SearchLimits, SearchOptions};
+use crate::tt::TTable;
+
+use std::io::Write;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+// milliseconds left on the clocks of white and black, and the increment
+// added after each move
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Clocks {
+    pub remaining: [u128; 2],
+    pub inc: u128,
+}
+
+impl Clocks {
+    // parses a time control such as "60000+500"
+    pub fn parse(s: &str) -> Option<Clocks> {
+        let mut parts = s.splitn(2, '+');
+        let base = parts.next()?.parse().ok()?;
+        let inc = parts.next().map_or(Some(0), |s| s.parse().ok())?;
+
+        Some(Clocks {
+            remaining: [base, base],
+            inc,
+        })
+    }
+}
+
+pub trait Player {
+    fn name(&self) -> &str;
+
+    fn new_game(&mut self);
+
+    // the move to play in pos, which was reached by playing moves from the
+    // FEN start, in coordinate notation
+    fn go(&mut self, start: &str, moves: &[String], pos: &Position, clocks: Option<&Clocks>) -> Option<String>;
+}
+
+pub struct Engine {
+    pub name: String,
+    pub limits: SearchLimits,
+    pub options: SearchOptions,
+    pub tt: Option<Arc<TTable>>,
+    pub threads: usize,
+}
+
+impl Engine {
+    // parses a configuration such as "name=new,depth=5,hash=32,threads=2,lmr=off"
+    pub fn parse(s: &str) -> Option<Engine> {
+        let mut out = Engine {
+            name: s.to_string(),
+            limits: SearchLimits::default(),
+            options: SearchOptions::default(),
+            tt: None,
+            threads: 1,
+        };
+        let mut hash = 16;
+
+        for opt in s.split(',') {
+            let mut kv = opt.splitn(2, '=');
+            let (key, value) = (kv.next()?, kv.next()?);
+            let switch = || match value {"on" => Some(true), "off" => Some(false), _ => None};
+
+            match key {
+                "name"  => out.name = value.to_string(),
+                "time"  => out.limits.time = Some(value.parse().ok()?),
+                "depth" => out.limits.depth = Some(value.parse().ok()?),
+                "nodes" => out.limits.nodes = Some(value.parse().ok()?),
+                "hash"  => hash = value.parse().ok()?,
+                "threads" => out.threads = value.parse::<usize>().ok()?.max(1),
+                "order" => out.options.order_moves = switch()?,
+                "pvs"   => out.options.pvs = switch()?,
+                "null"  => out.options.null_move = switch()?,
+                "lmr"   => out.options.lmr = switch()?,
+                "aspiration" => out.options.aspiration = switch()?,
+                _ => return None,
+            }
+        }
+
+        if out.limits.time.is_none() && out.limits.depth.is_none() && out.limits.nodes.is_none() {
+            out.limits.time = Some(100);
+        }
+        out.tt = Some(Arc::new(TTable::new(hash)));
+        Some(out)
+    }
+}
+
+impl Player for Engine {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn new_game(&mut self) {
+        self.tt.as_ref().unwrap().clear();
+    }
+
+    fn go(&mut self, _: &str, _: &[String], pos: &Position, clocks: Option<&Clocks>) -> Option<String> {
+        let mut limits = self.limits.clone();
+        if let Some(c) = clocks {
+            limits.set_clock(c.remaining[pos.board.inverted as usize], c.inc, None);
+        }
+
+        let mut search = Search::with_tt(limits, self.tt.take().unwrap());
+        search.threads = self.threads;
+        search.options = self.options.clone();
+        let (m, _) = search.run(pos.clone());
+        self.tt = Some(search.tt);
+
+        m.map(|m| Moves::move_to_string(&m, pos.board.inverted))
+    }
+}
+
+// a configuration with a cmd starts an external engine
+pub fn parse_player(s: &str) -> Option<Box<dyn Player>> {
+    if s.split(',').any(|opt| opt.starts_with("cmd=")) {
+        Some(Box::new(External::parse(s)?))
+    } else {
+        Some(Box::new(Engine::parse(s)?))
+    }
+}
+
+// plays a game from fen until test_endgame decides it, or a player runs out
+// of time or fails to reply with a legal move
+pub fn play_game(tables: &Tables,
+                 fen: &str,
+                 white: &mut dyn Player,
+                 black: &mut dyn Player,
+                 mut clocks: Option<Clocks>) -> Game
+{
+    let mut pos = Position::from_fen(tables, fen);
+    let mut game = Game::new(fen);
+    let mut moves = Vec::new();
+
+    game.set_tag("Event", "selfplay");
+    game.set_tag("White", white.name());
+    game.set_tag("Black", black.name());
+    if let Some(c) = &clocks {
+        game.set_tag("TimeControl", &format!("{}+{}", c.remaining[0] as f64 / 1000.0, c.inc as f64 / 1000.0));
+    }
+
+    white.new_game();
+    black.new_game();
+
+    pos.set_moves();
+    while pos.test_endgame() == None {
+        let side = pos.board.inverted as usize;
+        let player: &mut dyn Player = if side == 1 {black} else {white};
+        let start = SystemTime::now();
+
+        let reply = player.go(fen, &moves, &pos, clocks.as_ref());
+        let elapsed = start.elapsed().map(|d| d.as_millis()).unwrap_or(0);
+
+        // a loss for the side to move
+        let lost = if side == 1 {"1-0"} else {"0-1"};
+
+        if let Some(c) = clocks.as_mut() {
+            if elapsed > c.remaining[side] {
+                game.set_tag("Termination", "time forfeit");
+                game.set_result(lost);
+                return game;
+            }
+            c.remaining[side] = c.remaining[side] - elapsed + c.inc;
+        }
+
+        let legal = pos.gen_moves();
+        let m =
+            match reply.as_ref().and_then(|s| Moves::string_to_move(s, &pos.board)) {
+                Some(m) if legal.contains(&m) => m,
+                _ => {
+                    let comment = format!("{} played {}", player.name(), reply.as_deref().unwrap_or("nothing"));
+                    match game.line.moves.last_mut() {
+                        Some(n) => n.comment = Some(comment),
+                        None => game.line.comment = Some(comment),
+                    }
+                    game.set_tag("Termination", "rules infraction");
+                    game.set_result(lost);
+                    return game;
+                }
+            };
+
+        moves.push(Moves::move_to_string(&m, pos.board.inverted));
+        game.push(&pos, &m);
+        pos.do_move(&m);
+        pos.board.invert();
+        pos.set_moves();
+    }
+
+    game.set_result(result_string(pos.test_endgame()));
+    game
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Stats {
+    pub wins: usize,
+    pub draws: usize,
+    pub losses: usize,
+}
+
+fn logistic_elo(p: f64) -> f64 {
+    -400.0 * (1.0 / p - 1.0).log10()
+}
+
+fn expected_score(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sprt {
+    H0,
+    H1,
+    Continue,
+}
+
+impl Stats {
+    pub fn games(&self) -> usize {
+        self.wins + self.draws + self.losses
+    }
+
+    pub fn add(&mut self, result: &str, white: bool) {
+        match (result, white) {
+            ("1-0", true) | ("0-1", false) => self.wins += 1,
+            ("1-0", false) | ("0-1", true) => self.losses += 1,
+            _ => self.draws += 1,
+        }
+    }
+
+    pub fn score(&self) -> f64 {
+        (self.wins as f64 + self.draws as f64 / 2.0) / self.games() as f64
+    }
+
+    // variance of a single game's score
+    fn variance(&self) -> f64 {
+        let p = self.score();
+        let n = self.games() as f64;
+
+        (self.wins as f64 * (1.0 - p).powi(2) +
+         self.draws as f64 * (0.5 - p).powi(2) +
+         self.losses as f64 * p.powi(2)) / n
+    }
+
+    // Elo difference and the half width of its 95% confidence interval, None
+    // while one side has scored every point
+    pub fn elo(&self) -> Option<(f64, f64)> {
+        let p = self.score();
+        if !(p > 0.0 && p < 1.0) {
+            return None;
+        }
+
+        let margin = 1.96 * (self.variance() / self.games() as f64).sqrt();
+        let bound = |p: f64| logistic_elo(p.clamp(f64::EPSILON, 1.0 - f64::EPSILON));
+
+        Some((logistic_elo(p), (bound(p + margin) - bound(p - margin)) / 2.0))
+    }
+
+    // log likelihood ratio of elo1 against elo0, using the normal
+    // approximation of the trinomial model
+    pub fn llr(&self, elo0: f64, elo1: f64) -> f64 {
+        let var = self.variance();
+        if self.games() == 0 || var == 0.0 {
+            return 0.0;
+        }
+
+        let (s0, s1) = (expected_score(elo0), expected_score(elo1));
+        (s1 - s0) * (2.0 * self.score() - s0 - s1) * self.games() as f64 / (2.0 * var)
+    }
+
+    pub fn sprt(&self, elo0: f64, elo1: f64, alpha: f64, beta: f64) -> Sprt {
+        let llr = self.llr(elo0, elo1);
+
+        if llr >= ((1.0 - beta) / alpha).ln() {
+            Sprt::H1
+        } else if llr <= (beta / (1.0 - alpha)).ln() {
+            Sprt::H0
+        } else {
+            Sprt::Continue
+        }
+    }
+}
+
+fn read_openings(tables: &Tables, path: &str) -> std::io::Result<Vec<String>> {
+    let text = std::fs::read_to_string(path)?;
+
+    Ok(text.lines()
+        .filter(|l| !l.trim().is_empty())
+        .filter_map(|l| {
+            match Position::try_from_fen(tables, l) {
+                Ok(p) => Some(p.to_fen()),
+                Err(_) => Epd::parse(l).ok()?.position(tables).ok().map(|p| p.to_fen()),
+            }
+        })
+        .collect())
+}
+
+// chess_engine selfplay <config> <config> [openings <file>] [games <n>]
+//                       [tc <ms>+<inc>] [pgn <file>] [sprt <elo0> <elo1>]
+pub fn run(args: &[String]) {
+    let tables = new_tables();
+    let usage = "usage: selfplay <config> <config> [openings <file>] [games <n>] [tc <ms>+<inc>] [pgn <file>] [sprt <elo0> <elo1>]";
+
+    let mut players =
+        match (args.get(0).and_then(|s| parse_player(s)), args.get(1).and_then(|s| parse_player(s))) {
+            (Some(a), Some(b)) => [a, b],
+            _ => return eprintln!("{}", usage),
+        };
+
+    let mut openings = vec![START_FEN.to_string()];
+    let mut games = 100;
+    let mut pgn = None;
+    let mut clocks = None;
+    let (mut elo0, mut elo1) = (0.0, 5.0);
+
+    let mut i = 2;
+    while i < args.len() {
+        let arg = |n: usize| args.get(i + n).map(|s| s.as_str()).unwrap_or("");
+
+        match (arg(0), arg(1).parse::<f64>().ok()) {
+            ("openings", _) => {
+                openings = match read_openings(&tables, arg(1)) {
+                    Ok(o) => o,
+                    Err(e) => return eprintln!("{}: {}", arg(1), e),
+                };
+            },
+            ("games", Some(n)) => games = n as usize,
+            ("tc", _) => {
+                clocks = match Clocks::parse(arg(1)) {
+                    Some(c) => Some(c),
+                    None => return eprintln!("{}", usage),
+                };
+            },
+            ("pgn", _) => {
+                pgn = match std::fs::File::create(arg(1)) {
+                    Ok(f) => Some(f),
+                    Err(e) => return eprintln!("{}: {}", arg(1), e),
+                };
+            },
+            ("sprt", Some(e)) => {
+                elo0 = e;
+                elo1 = match arg(2).parse() {
+                    Ok(e) => e,
+                    Err(_) => return eprintln!("{}", usage),
+                };
+                i += 1;
+            },
+            _ => return eprintln!("{}", usage),
+        }
+        i += 2;
+    }
+
+    let mut stats = Stats::default();
+    let mut verdict = Sprt::Continue;
+
+    // each opening is played twice with colors swapped
+    'outer: for opening in openings.iter().cycle() {
+        for first_white in [true, false].iter() {
+            if stats.games() >= games {
+                break 'outer;
+            }
+
+            let [a, b] = &mut players;
+            let game =
+                if *first_white {
+                    play_game(&tables, opening, a.as_mut(), b.as_mut(), clocks)
+                } else {
+                    play_game(&tables, opening, b.as_mut(), a.as_mut(), clocks)
+                };
+
+            stats.add(&game.result, *first_white);
+            if let Some(f) = pgn.as_mut() {
+                writeln!(f, "{}", game).unwrap();
+            }
+
+            let elo = stats.elo().map_or("n/a".to_string(), |(e, m)| format!("{:.1} +/- {:.1}", e, m));
+            println!("Games: {}  W/D/L: {}/{}/{}  Elo: {}  LLR: {:.2}",
+                     stats.games(), stats.wins, stats.draws, stats.losses,
+                     elo, stats.llr(elo0, elo1));
+
+            verdict = stats.sprt(elo0, elo1, 0.05, 0.05);
+            if verdict != Sprt::Continue {
+                break 'outer;
+            }
+        }
+    }
+
+    println!();
+    println!("{} vs {}", players[0].name(), players[1].name());
+    match verdict {
+        Sprt::H1 => println!("SPRT: H1 accepted, elo >= {}", elo1),
+        Sprt::H0 => println!("SPRT: H0 accepted, elo <= {}", elo0),
+        Sprt::Continue => println!("SPRT: inconclusive after {} games", stats.games()),
+    }
+}
@@ -0,0 +1,1074 @@
+use crate::board::Board;
+use crate::movegen::{Move, Move::*};
+use crate::position::Position;
+use crate::search::MATE;
+
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+// score of a tablebase win, below any mate score
+pub const TB_WIN: i32 = MATE - 2000;
+
+// Cursed wins and blessed losses are wins and losses that the fifty move
+// rule turns into draws.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Wdl {
+    Loss,
+    BlessedLoss,
+    Draw,
+    CursedWin,
+    Win,
+}
+
+impl Wdl {
+    // the result for the other player
+    pub fn neg(self) -> Wdl {
+        match self {
+            Wdl::Loss        => Wdl::Win,
+            Wdl::BlessedLoss => Wdl::CursedWin,
+            Wdl::Draw        => Wdl::Draw,
+            Wdl::CursedWin   => Wdl::BlessedLoss,
+            Wdl::Win         => Wdl::Loss,
+        }
+    }
+
+    fn from_value(v: i32) -> Wdl {
+        match v {
+            -2 => Wdl::Loss,
+            -1 => Wdl::BlessedLoss,
+            1  => Wdl::CursedWin,
+            2  => Wdl::Win,
+            _  => Wdl::Draw,
+        }
+    }
+
+    fn sign(self) -> i32 {
+        (self as i32 - 2).signum()
+    }
+
+    // dtz of a position where the best move zeroes the fifty move counter
+    fn before_zeroing(self) -> i32 {
+        match self {
+            Wdl::Win         => 1,
+            Wdl::CursedWin   => 101,
+            Wdl::Draw        => 0,
+            Wdl::BlessedLoss => -101,
+            Wdl::Loss        => -1,
+        }
+    }
+
+    // search score for the player to move, ply plies from the root
+    pub fn score(self, ply: usize) -> i32 {
+        match self {
+            Wdl::Win  =>  TB_WIN - ply as i32,
+            Wdl::Loss => -TB_WIN + ply as i32,
+            _ => 0,
+        }
+    }
+}
+
+// Tablebase results from the point of view of the player to move. Dtz is the
+// number of plies to the next capture or pawn move with best play, positive
+// if the player to move wins and negative if they lose.
+pub trait Probe: Send + Sync {
+    fn max_pieces(&self) -> usize;
+
+    fn probe_wdl(&self, pos: &Position) -> Option<Wdl>;
+
+    fn probe_dtz(&self, pos: &Position) -> Option<i32>;
+}
+
+fn is_capture(board: &Board, m: &Move) -> bool {
+    match m {
+        Basic(_, to) | Promotion(_, _, to) => board.other & (1 << to) != 0,
+        En_passant(_, _) => true,
+        _ => false,
+    }
+}
+
+fn is_pawn_move(board: &Board, m: &Move) -> bool {
+    match m {
+        Basic(from, _) => board.pawns() & (1 << from) != 0,
+        En_passant(_, _) | Promotion(_, _, _) => true,
+        _ => false,
+    }
+}
+
+// tables only hold positions without castling rights
+pub fn can_probe(tb: &dyn Probe, pos: &Position) -> bool {
+    let b = &pos.board;
+
+    b.all().count_ones() as usize <= tb.max_pieces() &&
+        !b.castle_curr.iter().chain(b.castle_other.iter()).any(|c| *c)
+}
+
+// Picks the move keeping the best tablebase result: the fastest win, or the
+// slowest loss. Mates and zeroing moves are counted as zero plies to the
+// next zeroing move.
+pub fn root_move(tb: &dyn Probe, pos: &Position) -> Option<(Move, Wdl)> {
+    let mut pos = pos.clone();
+    pos.set_moves();
+
+    if !can_probe(tb, &pos) {
+        return None;
+    }
+
+    let mut best: Option<(Move, Wdl, i32)> = None;
+
+    for m in pos.gen_moves() {
+        let zeroing = is_capture(&pos.board, &m) || is_pawn_move(&pos.board, &m);
+
+        let mut p = pos.clone();
+        p.do_move(&m);
+        p.board.invert();
+        p.set_moves();
+
+        let (wdl, dist) =
+            if p.gen_moves().is_empty() {
+                (if p.is_in_check() {Wdl::Win} else {Wdl::Draw}, 0)
+            } else {
+                let dtz = tb.probe_dtz(&p)?;
+                (tb.probe_wdl(&p)?.neg(), if zeroing {0} else {dtz.abs()})
+            };
+
+        // a later result is better if it wins faster or loses slower
+        let rank = |wdl: Wdl, dist: i32| (wdl, if wdl > Wdl::Draw {-dist} else {dist});
+
+        if best.as_ref().map_or(true, |(_, w, d)| rank(wdl, dist) > rank(*w, *d)) {
+            best = Some((m, wdl, dist));
+        }
+    }
+
+    best.map(|(m, wdl, _)| (m, wdl))
+}
+
+const WDL_MAGIC: [u8; 4] = [0x71, 0xe8, 0x23, 0x5d];
+const DTZ_MAGIC: [u8; 4] = [0xd7, 0x66, 0x0c, 0xa5];
+
+// flags of a table part
+const STM: u8 = 1;
+const MAPPED: u8 = 2;
+const WIN_PLIES: u8 = 4;
+const LOSS_PLIES: u8 = 8;
+const WIDE: u8 = 16;
+const SINGLE_VALUE: u8 = 128;
+
+// Tables used to turn a position into an index. Squares go from a1 = 0 to
+// h8 = 63, and pieces are numbered as in Piece with 8 added for black.
+struct Maps {
+    // ways to place k pieces on n squares
+    binomial: [[u64; 64]; 6],
+    // squares below the a1-h8 diagonal
+    b1h1h7: [u64; 64],
+    // the a1-d1-d4 triangle, diagonal last
+    a1d1d4: [u64; 64],
+    // the 462 placements of two kings with the first in a1-d1-d4
+    kk: [[u64; 64]; 10],
+    // squares of a leading pawn, the ones nearer the edge and lower higher
+    pawns: [u64; 64],
+    lead_pawn_idx: [[u64; 64]; 6],
+    lead_pawns_size: [[u64; 4]; 6],
+}
+
+static MAPS: Maps = gen_maps();
+
+const fn off_diagonal(sq: usize) -> i32 {
+    (sq / 8) as i32 - (sq % 8) as i32
+}
+
+const fn gen_maps() -> Maps {
+    let mut m = Maps {
+        binomial: [[0; 64]; 6],
+        b1h1h7: [0; 64],
+        a1d1d4: [0; 64],
+        kk: [[0; 64]; 10],
+        pawns: [0; 64],
+        lead_pawn_idx: [[0; 64]; 6],
+        lead_pawns_size: [[0; 4]; 6],
+    };
+
+    let mut code = 0;
+    let mut s = 0;
+    while s < 64 {
+        if off_diagonal(s) < 0 {
+            m.b1h1h7[s] = code;
+            code += 1;
+        }
+        s += 1;
+    }
+
+    let mut diagonal = [0; 4];
+    let mut n = 0;
+    code = 0;
+    s = 0;
+    while s <= 27 {
+        if off_diagonal(s) < 0 && s % 8 <= 3 {
+            m.a1d1d4[s] = code;
+            code += 1;
+        } else if off_diagonal(s) == 0 && s % 8 <= 3 {
+            diagonal[n] = s;
+            n += 1;
+        }
+        s += 1;
+    }
+    let mut i = 0;
+    while i < n {
+        m.a1d1d4[diagonal[i]] = code;
+        code += 1;
+        i += 1;
+    }
+
+    // placements with both kings on the diagonal come last
+    let mut both = [(0, 0); 32];
+    n = 0;
+    code = 0;
+    let mut k = 0;
+    while k < 10 {
+        let mut s1 = 0;
+        while s1 <= 27 {
+            if m.a1d1d4[s1] == k as u64 && (k != 0 || s1 == 1) {
+                let mut s2 = 0;
+                while s2 < 64 {
+                    let files = (s1 % 8) as i32 - (s2 % 8) as i32;
+                    let ranks = (s1 / 8) as i32 - (s2 / 8) as i32;
+
+                    if files * files <= 1 && ranks * ranks <= 1 {
+                        // kings next to each other
+                    } else if off_diagonal(s1) == 0 && off_diagonal(s2) > 0 {
+                        // mirrored by the diagonal
+                    } else if off_diagonal(s1) == 0 && off_diagonal(s2) == 0 {
+                        both[n] = (k, s2);
+                        n += 1;
+                    } else {
+                        m.kk[k][s2] = code;
+                        code += 1;
+                    }
+                    s2 += 1;
+                }
+            }
+            s1 += 1;
+        }
+        k += 1;
+    }
+    i = 0;
+    while i < n {
+        m.kk[both[i].0][both[i].1] = code;
+        code += 1;
+        i += 1;
+    }
+
+    m.binomial[0][0] = 1;
+    n = 1;
+    while n < 64 {
+        k = 0;
+        while k < 6 && k <= n {
+            m.binomial[k][n] = if k > 0 {m.binomial[k - 1][n - 1]} else {0} +
+                               if k < n {m.binomial[k][n - 1]} else {0};
+            k += 1;
+        }
+        n += 1;
+    }
+
+    let mut available: i64 = 47;
+    let mut count = 1;
+    while count <= 5 {
+        let mut file = 0;
+        while file < 4 {
+            let mut idx = 0;
+            let mut rank = 1;
+            while rank <= 6 {
+                let sq = file + rank * 8;
+                if count == 1 {
+                    m.pawns[sq] = available as u64;
+                    m.pawns[sq ^ 7] = (available - 1) as u64;
+                    available -= 2;
+                }
+                m.lead_pawn_idx[count][sq] = idx;
+                idx += m.binomial[count - 1][m.pawns[sq] as usize];
+                rank += 1;
+            }
+            m.lead_pawns_size[count][file] = idx;
+            file += 1;
+        }
+        count += 1;
+    }
+
+    m
+}
+
+fn byte(data: &[u8], p: usize) -> Option<usize> {
+    data.get(p).map(|b| *b as usize)
+}
+
+fn u16_le(data: &[u8], p: usize) -> Option<usize> {
+    Some(byte(data, p)? | byte(data, p + 1)? << 8)
+}
+
+fn u32_le(data: &[u8], p: usize) -> Option<usize> {
+    Some(u16_le(data, p)? | u16_le(data, p + 2)? << 16)
+}
+
+// n bytes read as a big-endian number, with zeros past the end
+fn big_endian(data: &[u8], p: usize, n: usize) -> u64 {
+    (p..p + n).fold(0, |acc, i| acc << 8 | *data.get(i).unwrap_or(&0) as u64)
+}
+
+// A table is split into parts by the side to move and, with pawns, by the
+// file of the leading pawn. Each part has its own piece order and its own
+// compressed data: the values are stored as canonical Huffman codes of
+// symbols, which stand either for a value or for a pair of other symbols.
+#[derive(Clone, Default)]
+struct Part {
+    flags: u8,
+    pieces: [u8; 7],
+    // sizes of the groups of pieces indexed together, ending in 0
+    group_len: [usize; 8],
+    group_idx: [u64; 8],
+    block_size: usize,
+    span: u64,
+    sparse_index: usize,
+    sparse_size: usize,
+    block_lengths: usize,
+    block_lengths_size: usize,
+    blocks: usize,
+    data: usize,
+    // the value itself for a single valued part
+    min_sym_len: usize,
+    lowest_sym: usize,
+    base64: Vec<u64>,
+    // number of values a symbol stands for, less one
+    symlen: Vec<usize>,
+    btree: usize,
+    map_idx: [usize; 4],
+}
+
+// what a table's name tells about it
+#[derive(Clone, Copy)]
+struct Material {
+    pieces: usize,
+    // pawns of the leading color and of the other one
+    pawns: [usize; 2],
+    has_pawns: bool,
+    // some piece other than a king is alone of its kind and color
+    unique: bool,
+    symmetric: bool,
+}
+
+impl Part {
+    fn set_groups(&mut self, material: &Material, order: [usize; 2], file: usize) {
+        let mut n = 0;
+        let mut first_len: i32 = if material.has_pawns {0} else if material.unique {3} else {2};
+        self.group_len[0] = 1;
+
+        for i in 1..material.pieces {
+            first_len -= 1;
+            if first_len > 0 || self.pieces[i] == self.pieces[i - 1] {
+                self.group_len[n] += 1;
+            } else {
+                n += 1;
+                self.group_len[n] = 1;
+            }
+        }
+        n += 1;
+        self.group_len[n] = 0;
+
+        // the groups are indexed in the order the table gives, with the
+        // leading pieces at order[0] and the other color's pawns at order[1]
+        let pp = material.has_pawns && material.pawns[1] > 0;
+        let mut next = if pp {2} else {1};
+        let mut free = 64 - self.group_len[0] - if pp {self.group_len[1]} else {0};
+        let mut idx = 1;
+        let mut k = 0;
+
+        while next < n || k == order[0] || k == order[1] {
+            if k == order[0] {
+                self.group_idx[0] = idx;
+                idx *= if material.has_pawns {
+                    MAPS.lead_pawns_size[self.group_len[0]][file]
+                } else if material.unique {
+                    31332
+                } else {
+                    462
+                };
+            } else if k == order[1] {
+                self.group_idx[1] = idx;
+                idx *= MAPS.binomial[self.group_len[1]][48 - self.group_len[0]];
+            } else {
+                self.group_idx[next] = idx;
+                idx *= MAPS.binomial[self.group_len[next]][free];
+                free -= self.group_len[next];
+                next += 1;
+            }
+            k += 1;
+        }
+        self.group_idx[n] = idx;
+    }
+
+    // reads the sizes and the symbol tree of the compressed data at p, and
+    // returns where they end
+    fn set_sizes(&mut self, data: &[u8], mut p: usize) -> Option<usize> {
+        self.flags = byte(data, p)? as u8;
+        p += 1;
+
+        if self.flags & SINGLE_VALUE != 0 {
+            self.min_sym_len = byte(data, p)?;
+            return Some(p + 1);
+        }
+
+        let size = self.group_idx[self.group_len.iter().position(|l| *l == 0)?];
+        self.block_size = 1usize.checked_shl(byte(data, p)? as u32)?;
+        self.span = 1u64.checked_shl(byte(data, p + 1)? as u32)?;
+        self.sparse_size = ((size + self.span - 1) / self.span) as usize;
+        self.blocks = u32_le(data, p + 3)?;
+        self.block_lengths_size = self.blocks + byte(data, p + 2)?;
+        let max_sym_len = byte(data, p + 7)?;
+        self.min_sym_len = byte(data, p + 8)?;
+        p += 9;
+
+        // Codes are canonical with longer codes lower, so that base64[i] is
+        // the lowest code of length min_sym_len + i, left aligned in 64 bits.
+        self.lowest_sym = p;
+        let lengths = (max_sym_len + 1).checked_sub(self.min_sym_len).filter(|n| *n > 0)?;
+        self.base64 = vec![0; lengths];
+        for i in (0..lengths - 1).rev() {
+            let lowest = |i| u16_le(data, p + 2 * i).map(|s| s as i64);
+            self.base64[i] = (self.base64[i + 1] as i64 + lowest(i)? - lowest(i + 1)?) as u64 / 2;
+        }
+        for i in 0..lengths {
+            self.base64[i] = self.base64[i].checked_shl((64 - i - self.min_sym_len) as u32).unwrap_or(0);
+        }
+        p += 2 * lengths;
+
+        let symbols = u16_le(data, p)?;
+        p += 2;
+        self.btree = p;
+        if p + 3 * symbols > data.len() {
+            return None;
+        }
+
+        self.symlen = vec![0; symbols];
+        let mut visited = vec![false; symbols];
+        for s in 0..symbols {
+            if !visited[s] {
+                self.symlen[s] = self.set_symlen(data, s, &mut visited)?;
+            }
+        }
+
+        Some(p + 3 * symbols + (symbols & 1))
+    }
+
+    fn set_symlen(&mut self, data: &[u8], s: usize, visited: &mut Vec<bool>) -> Option<usize> {
+        visited[s] = true;
+        let (left, right) = self.pair(data, s);
+
+        if right == 0xfff {
+            return Some(0);
+        }
+        for c in [left, right].iter() {
+            if !*visited.get(*c)? {
+                self.symlen[*c] = self.set_symlen(data, *c, visited)?;
+            }
+        }
+
+        Some(self.symlen[left] + self.symlen[right] + 1)
+    }
+
+    // the two symbols a symbol stands for, or the value and 0xfff for a
+    // single value
+    fn pair(&self, data: &[u8], s: usize) -> (usize, usize) {
+        let lr = &data[self.btree + 3 * s..];
+        ((lr[1] as usize & 0xf) << 8 | lr[0] as usize, (lr[2] as usize) << 4 | lr[1] as usize >> 4)
+    }
+
+    fn block_length(&self, data: &[u8], block: usize) -> Option<i64> {
+        if block >= self.block_lengths_size {
+            return None;
+        }
+        u16_le(data, self.block_lengths + 2 * block).map(|l| l as i64)
+    }
+
+    fn decompress(&self, data: &[u8], idx: u64) -> Option<usize> {
+        if self.flags & SINGLE_VALUE != 0 {
+            return Some(self.min_sym_len);
+        }
+
+        // The sparse index gives the block and offset of every span'th
+        // value, from which the block lengths lead to the one wanted.
+        let k = (idx / self.span) as usize;
+        if k >= self.sparse_size {
+            return None;
+        }
+        let entry = self.sparse_index + 6 * k;
+        let mut block = u32_le(data, entry)?;
+        let mut offset = u16_le(data, entry + 4)? as i64;
+        offset += (idx % self.span) as i64 - (self.span / 2) as i64;
+
+        while offset < 0 {
+            block = block.checked_sub(1)?;
+            offset += self.block_length(data, block)? + 1;
+        }
+        while offset > self.block_length(data, block)? {
+            offset -= self.block_length(data, block)? + 1;
+            block += 1;
+        }
+
+        // skip whole symbols until the one holding the value
+        let mut p = self.data + block * self.block_size;
+        let mut buf = big_endian(data, p, 8);
+        let mut bits = 64;
+        p += 8;
+
+        let mut sym;
+        loop {
+            let mut len = 0;
+            while buf < self.base64[len] {
+                len += 1;
+            }
+            sym = ((buf - self.base64[len]) >> (64 - len - self.min_sym_len)) as usize +
+                  u16_le(data, self.lowest_sym + 2 * len)?;
+            if offset < *self.symlen.get(sym)? as i64 + 1 {
+                break;
+            }
+
+            offset -= self.symlen[sym] as i64 + 1;
+            len += self.min_sym_len;
+            buf <<= len;
+            bits -= len;
+            if bits <= 32 {
+                bits += 32;
+                buf |= big_endian(data, p, 4) << (64 - bits);
+                p += 4;
+            }
+        }
+
+        // and go down its pairs to the value
+        while self.symlen[sym] != 0 {
+            let (left, right) = self.pair(data, sym);
+
+            if offset < *self.symlen.get(left)? as i64 + 1 {
+                sym = left;
+            } else {
+                offset -= self.symlen[left] as i64 + 1;
+                sym = right;
+            }
+            self.symlen.get(sym)?;
+        }
+
+        Some(self.pair(data, sym).0)
+    }
+}
+
+// what probing a table can give: a value, or for DTZ tables, which only
+// hold one side to move, that the position is not in it
+enum Lookup {
+    Value(i32),
+    OtherSide,
+}
+
+struct Table {
+    data: Vec<u8>,
+    dtz: bool,
+    material: Material,
+    // by side to move and file
+    parts: Vec<Vec<Part>>,
+    map: usize,
+}
+
+impl Table {
+    fn new(data: Vec<u8>, name: &str, dtz: bool) -> Option<Table> {
+        let (white, black) = name.split_at(name.find('v')?);
+        let black = &black[1..];
+        let count = |side: &str, c| side.matches(c).count();
+
+        // the color with fewer pawns leads, or white with as many
+        let (wp, bp) = (count(white, 'P'), count(black, 'P'));
+        let pawns = if bp == 0 || (wp > 0 && bp >= wp) {[wp, bp]} else {[bp, wp]};
+
+        let material = Material {
+            pieces: white.len() + black.len(),
+            pawns,
+            has_pawns: wp + bp > 0,
+            unique: [white, black].iter().any(|side| "QRBNP".chars().any(|c| count(side, c) == 1)),
+            symmetric: white == black,
+        };
+
+        let magic = if dtz {DTZ_MAGIC} else {WDL_MAGIC};
+        if data.get(0..4)? != &magic[..] || (byte(&data, 4)? & 2 != 0) != material.has_pawns || material.pieces > 7 {
+            return None;
+        }
+
+        let sides = if dtz || material.symmetric {1} else {2};
+        let files = if material.has_pawns {4} else {1};
+        let pp = material.has_pawns && material.pawns[1] > 0;
+        let mut parts = vec![vec![Part::default(); files]; sides];
+        let mut p = 5;
+
+        for f in 0..files {
+            let first = byte(&data, p)?;
+            let second = if pp {byte(&data, p + 1)?} else {0xff};
+            let order = [[first & 0xf, second & 0xf], [first >> 4, second >> 4]];
+            p += 1 + pp as usize;
+
+            for k in 0..material.pieces {
+                let b = byte(&data, p)?;
+                for (i, side) in parts.iter_mut().enumerate() {
+                    side[f].pieces[k] = if i == 0 {b & 0xf} else {b >> 4} as u8;
+                }
+                p += 1;
+            }
+            for (i, side) in parts.iter_mut().enumerate() {
+                side[f].set_groups(&material, order[i], f);
+            }
+        }
+        p += p & 1;
+
+        for f in 0..files {
+            for side in parts.iter_mut() {
+                p = side[f].set_sizes(&data, p)?;
+            }
+        }
+
+        // DTZ values can be mapped through a table for each result
+        let map = p;
+        if dtz {
+            for part in parts[0].iter_mut() {
+                if part.flags & MAPPED == 0 {
+                    continue;
+                }
+                if part.flags & WIDE != 0 {
+                    p += p & 1;
+                    for i in 0..4 {
+                        part.map_idx[i] = (p - map) / 2 + 1;
+                        p += 2 * u16_le(&data, p)? + 2;
+                    }
+                } else {
+                    for i in 0..4 {
+                        part.map_idx[i] = p - map + 1;
+                        p += byte(&data, p)? + 1;
+                    }
+                }
+            }
+            p += p & 1;
+        }
+
+        for f in 0..files {
+            for side in parts.iter_mut() {
+                side[f].sparse_index = p;
+                p += 6 * side[f].sparse_size;
+            }
+        }
+        for f in 0..files {
+            for side in parts.iter_mut() {
+                side[f].block_lengths = p;
+                p += 2 * side[f].block_lengths_size;
+            }
+        }
+        for f in 0..files {
+            for side in parts.iter_mut() {
+                p = (p + 63) & !63;
+                side[f].data = p;
+                p += side[f].blocks * side[f].block_size;
+            }
+        }
+
+        if p > data.len() {
+            return None;
+        }
+
+        Some(Table {data, dtz, material, parts, map})
+    }
+
+    // The part and index of the position, with white to move. With flip set
+    // the table has the colors the other way around, so they are swapped and
+    // the board mirrored.
+    fn index(&self, board: &Board, flip: bool) -> Option<(&Part, usize, u64)> {
+        let material = &self.material;
+        let (flip_color, flip_sq) = if flip {(8, 56)} else {(0, 0)};
+        let mut squares = [0; 7];
+        let mut pieces = [0; 7];
+        let mut size = 0;
+        let mut lead = 0;
+        let mut file = 0;
+        let mut lead_pawns = 0;
+
+        if board.all().count_ones() as usize != material.pieces {
+            return None;
+        }
+
+        let code = |s: usize| board.get_loc_piece(s as u8) as u8 | if board.other & (1 << s) != 0 {8} else {0};
+        let bits = |mut b: u64| std::iter::from_fn(move || {
+            let s = b.trailing_zeros() as usize;
+            b &= b.wrapping_sub(1);
+            if s < 64 {Some(s)} else {None}
+        });
+
+        // the pawns of the leading color come first, the one with the
+        // highest map value in front, and its file picks the part
+        if material.has_pawns {
+            let color = if (self.parts[0][0].pieces[0] ^ flip_color) & 8 != 0 {board.other} else {board.curr};
+            lead_pawns = board.pawns() & color;
+            for s in bits(lead_pawns) {
+                squares[size] = s ^ flip_sq;
+                size += 1;
+            }
+            lead = size;
+
+            let first = (0..lead).rev().max_by_key(|i| MAPS.pawns[squares[*i]])?;
+            squares.swap(0, first);
+            file = (squares[0] % 8).min(7 - squares[0] % 8);
+        }
+
+        for s in bits(board.all() & !lead_pawns) {
+            squares[size] = s ^ flip_sq;
+            pieces[size] = code(s) ^ flip_color;
+            size += 1;
+        }
+
+        let part = &self.parts[flip as usize % self.parts.len()][file];
+
+        // pieces go in the order of the part
+        for i in lead..size - 1 {
+            if let Some(j) = (i + 1..size).find(|j| part.pieces[i] == pieces[*j]) {
+                pieces.swap(i, j);
+                squares.swap(i, j);
+            }
+        }
+
+        if squares[0] % 8 > 3 {
+            for s in squares[..size].iter_mut() {
+                *s ^= 7;
+            }
+        }
+
+        let mut idx;
+        if material.has_pawns {
+            idx = MAPS.lead_pawn_idx[lead][squares[0]];
+            squares[1..lead].sort_by_key(|s| MAPS.pawns[*s]);
+            for i in 1..lead {
+                idx += MAPS.binomial[i][MAPS.pawns[squares[i]] as usize];
+            }
+        } else {
+            // without pawns the leading piece goes below rank 5, and the
+            // first of the leading group off the a1-h8 diagonal below it
+            if squares[0] / 8 > 3 {
+                for s in squares[..size].iter_mut() {
+                    *s ^= 56;
+                }
+            }
+            for i in 0..part.group_len[0] {
+                let off = off_diagonal(squares[i]);
+                if off == 0 {
+                    continue;
+                }
+                if off > 0 {
+                    for s in squares[i..size].iter_mut() {
+                        *s = (*s >> 3 | *s << 3) & 63;
+                    }
+                }
+                break;
+            }
+
+            let s = [squares[0] as u64, squares[1] as u64, squares[2] as u64];
+            let rank = |i: usize| s[i] / 8;
+            let adjust1 = (s[1] > s[0]) as u64;
+            let adjust2 = (s[2] > s[0]) as u64 + (s[2] > s[1]) as u64;
+
+            idx = if !material.unique {
+                MAPS.kk[MAPS.a1d1d4[squares[0]] as usize][squares[1]]
+            } else if off_diagonal(squares[0]) != 0 {
+                (MAPS.a1d1d4[squares[0]] * 63 + s[1] - adjust1) * 62 + s[2] - adjust2
+            } else if off_diagonal(squares[1]) != 0 {
+                (6 * 63 + rank(0) * 28 + MAPS.b1h1h7[squares[1]]) * 62 + s[2] - adjust2
+            } else if off_diagonal(squares[2]) != 0 {
+                6 * 63 * 62 + 4 * 28 * 62 + rank(0) * 7 * 28 + (rank(1) - adjust1) * 28 + MAPS.b1h1h7[squares[2]]
+            } else {
+                6 * 63 * 62 + 4 * 28 * 62 + 4 * 7 * 28 + rank(0) * 7 * 6 + (rank(1) - adjust1) * 6 + rank(2) - adjust2
+            };
+        }
+
+        // the other groups count the squares left by the ones before
+        idx *= part.group_idx[0];
+        let mut start = part.group_len[0];
+        let mut remaining_pawns = material.has_pawns && material.pawns[1] > 0;
+        let mut next = 1;
+
+        while part.group_len[next] != 0 {
+            let len = part.group_len[next];
+            squares[start..start + len].sort();
+
+            let mut n = 0;
+            for i in 0..len {
+                let sq = squares[start + i];
+                let below = squares[..start].iter().filter(|s| sq > **s).count();
+                n += MAPS.binomial[i + 1][(sq - below).checked_sub(8 * remaining_pawns as usize)?];
+            }
+
+            remaining_pawns = false;
+            idx += n * part.group_idx[next];
+            start += len;
+            next += 1;
+        }
+
+        Some((part, file, idx))
+    }
+
+    fn probe(&self, board: &Board, flip: bool, wdl: Wdl) -> Option<Lookup> {
+        let (part, _, idx) = self.index(board, flip)?;
+
+        if self.dtz && part.flags & STM != flip as u8 && !(self.material.symmetric && !self.material.has_pawns) {
+            return Some(Lookup::OtherSide);
+        }
+
+        let value = part.decompress(&self.data, idx)? as i32;
+        if !self.dtz {
+            return Some(Lookup::Value(value - 2));
+        }
+
+        // DTZ values are in moves unless the flags say plies
+        let mut value = value;
+        if part.flags & MAPPED != 0 {
+            let i = part.map_idx[[1, 3, 0, 2, 0][wdl as usize]] + value as usize;
+            value =
+                if part.flags & WIDE != 0 {
+                    u16_le(&self.data, self.map + 2 * i)?
+                } else {
+                    byte(&self.data, self.map + i)?
+                } as i32;
+        }
+        if (wdl == Wdl::Win && part.flags & WIN_PLIES == 0) ||
+           (wdl == Wdl::Loss && part.flags & LOSS_PLIES == 0) ||
+           wdl == Wdl::CursedWin || wdl == Wdl::BlessedLoss
+        {
+            value *= 2;
+        }
+
+        Some(Lookup::Value(value + 1))
+    }
+}
+
+// material of one color as the count of each piece but the king, four
+// bits each in the order of table names
+fn material(board: &Board, color: u64) -> u64 {
+    let b = board;
+    let counts = [
+        b.rook & b.bishop & color,
+        b.rook & !b.bishop & color,
+        b.bishop & !b.rook & color,
+        b.knight() & color,
+        b.pawns() & color,
+    ];
+
+    counts.iter().enumerate().map(|(i, bits)| (bits.count_ones() as u64) << (4 * i)).sum()
+}
+
+// the same for one side of a table name, such as KRP
+fn name_material(side: &str) -> u64 {
+    "QRBNP".chars().enumerate().map(|(i, c)| (side.matches(c).count() as u64) << (4 * i)).sum()
+}
+
+// the WDL and DTZ files of one material balance, by dtz
+#[derive(Default)]
+struct Entry {
+    name: String,
+    found: [bool; 2],
+    loaded: [OnceLock<Option<Table>>; 2],
+}
+
+// Syzygy tables found in a directory, by the material of both colors. A
+// table is read into memory the first time it is probed.
+pub struct Syzygy {
+    dir: PathBuf,
+    tables: HashMap<u64, Entry>,
+    max_pieces: usize,
+}
+
+impl Syzygy {
+    pub fn open(dir: &str) -> io::Result<Syzygy> {
+        let mut out = Syzygy {
+            dir: PathBuf::from(dir),
+            tables: HashMap::new(),
+            max_pieces: 0,
+        };
+
+        for entry in std::fs::read_dir(dir)? {
+            let name = entry?.file_name().to_string_lossy().to_string();
+
+            let (table, dtz) =
+                if let Some(t) = name.strip_suffix(".rtbw") {
+                    (t, false)
+                } else if let Some(t) = name.strip_suffix(".rtbz") {
+                    (t, true)
+                } else {
+                    continue;
+                };
+
+            if table.chars().all(|c| "KQRBNPv".contains(c)) && table.matches('v').count() == 1 {
+                let (white, black) = table.split_at(table.find('v').unwrap());
+                let key = name_material(white) | name_material(black) << 32;
+                let entry = out.tables.entry(key).or_insert_with(|| Entry {name: table.to_string(), ..Default::default()});
+
+                out.max_pieces = out.max_pieces.max(table.len() - 1);
+                entry.found[dtz as usize] = true;
+            }
+        }
+
+        Ok(out)
+    }
+
+    pub fn len(&self) -> usize {
+        self.tables.values().map(|e| e.found.iter().filter(|f| **f).count()).sum()
+    }
+
+    // the table for the material on the board, and whether it has the
+    // colors the other way around
+    fn table(&self, board: &Board, dtz: bool) -> Option<(&Table, bool)> {
+        let white = material(board, board.curr);
+        let black = material(board, board.other);
+        let find = |key| self.tables.get(&key).filter(|e| e.found[dtz as usize]);
+
+        let (entry, flip) =
+            match find(white | black << 32) {
+                Some(e) => (e, false),
+                None => (find(black | white << 32)?, true),
+            };
+
+        let table = entry.loaded[dtz as usize].get_or_init(|| {
+            let file = format!("{}.{}", entry.name, if dtz {"rtbz"} else {"rtbw"});
+            let data = std::fs::read(self.dir.join(file)).ok()?;
+            Table::new(data, &entry.name, dtz)
+        });
+
+        Some((table.as_ref()?, flip))
+    }
+
+    fn probe_table(&self, board: &Board, dtz: bool, wdl: Wdl) -> Option<Lookup> {
+        if board.all().count_ones() == 2 {
+            return Some(Lookup::Value(0));
+        }
+
+        let (table, flip) = self.table(board, dtz)?;
+        table.probe(board, flip, wdl)
+    }
+
+    // where a position with white to move is stored in its table
+    #[cfg(test)]
+    pub fn index(&self, board: &Board, dtz: bool) -> Option<u64> {
+        let (table, flip) = self.table(board, dtz)?;
+        table.index(board, flip).map(|(_, _, idx)| idx)
+    }
+
+    // Tables may hold any value where a capture is the best move, so the
+    // captures are searched first, and with zeroing also the pawn moves.
+    // Also returns whether one of those moves is the best.
+    fn search(&self, pos: &mut Position, zeroing: bool) -> Option<(Wdl, bool)> {
+        pos.set_moves();
+        let moves = pos.gen_moves();
+        let mut best = Wdl::Loss;
+        let mut searched = 0;
+
+        for m in moves.iter() {
+            if !is_capture(&pos.board, m) && !(zeroing && is_pawn_move(&pos.board, m)) {
+                continue;
+            }
+            searched += 1;
+
+            let undo = pos.do_move(m);
+            pos.board.invert();
+            let value = self.search(pos, false);
+            pos.board.invert();
+            pos.unmake_move(m, undo);
+
+            let value = value?.0.neg();
+            if value > best {
+                best = value;
+                if value == Wdl::Win {
+                    return Some((value, true));
+                }
+            }
+        }
+
+        let all = searched > 0 && searched == moves.len();
+        let value =
+            if all {
+                best
+            } else {
+                match self.probe_table(&pos.board, false, Wdl::Draw)? {
+                    Lookup::Value(v) => Wdl::from_value(v),
+                    Lookup::OtherSide => return None,
+                }
+            };
+
+        if best >= value {
+            Some((best, best > Wdl::Draw || all))
+        } else {
+            Some((value, false))
+        }
+    }
+
+    fn dtz(&self, pos: &mut Position) -> Option<i32> {
+        let (wdl, zeroing) = self.search(pos, true)?;
+
+        if wdl == Wdl::Draw {
+            return Some(0);
+        }
+        if zeroing {
+            return Some(wdl.before_zeroing());
+        }
+
+        if let Lookup::Value(dtz) = self.probe_table(&pos.board, true, wdl)? {
+            let cursed = if wdl == Wdl::CursedWin || wdl == Wdl::BlessedLoss {100} else {0};
+            return Some((dtz + cursed) * wdl.sign());
+        }
+
+        // the table holds the other side to move, so look one move ahead
+        let mut min = 0xffff;
+
+        for m in pos.gen_moves() {
+            let zeroing = is_capture(&pos.board, &m) || is_pawn_move(&pos.board, &m);
+
+            let undo = pos.do_move(&m);
+            pos.board.invert();
+            let dtz =
+                if zeroing {
+                    self.search(pos, false).map(|(wdl, _)| -wdl.before_zeroing())
+                } else {
+                    self.dtz(pos).map(|dtz| -dtz)
+                };
+            let mate = dtz == Some(1) && {
+                pos.set_moves();
+                pos.is_in_check() && pos.gen_moves().is_empty()
+            };
+            pos.board.invert();
+            pos.unmake_move(&m, undo);
+
+            let mut dtz = dtz?;
+            if mate {
+                min = 1;
+            }
+            if !zeroing {
+                dtz += dtz.signum();
+            }
+            if dtz < min && dtz.signum() == wdl.sign() {
+                min = dtz;
+            }
+        }
+
+        Some(if min == 0xffff {-1} else {min})
+    }
+}
+
+impl Probe for Syzygy {
+    fn max_pieces(&self) -> usize {
+        self.max_pieces
+    }
+
+    fn probe_wdl(&self, pos: &Position) -> Option<Wdl> {
+        self.search(&mut pos.clone(), false).map(|(wdl, _)| wdl)
+    }
+
+    fn probe_dtz(&self, pos: &Position) -> Option<i32> {
+        self.dtz(&mut pos.clone())
+    }
+}
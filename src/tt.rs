@@ -0,0 +1,143 @@
+use crate::board::Piece;
+use crate::movegen::{Move, Move::*};
+use crate::search::MATE;
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// scores this close to MATE are mates, and are stored relative to the node
+// they were found in rather than to the root
+pub const MATE_BOUND: i32 = MATE - 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Bound {
+    Exact,
+    Lower,
+    Upper,
+}
+
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub depth: u8,
+    pub bound: Bound,
+    pub score: i32,
+    pub mov: Option<Move>,
+}
+
+// Moves are packed into 18 bits: kind, promotion piece, from and to.
+// Zero is no move.
+fn pack_move(m: &Option<Move>) -> u64 {
+    let (kind, piece, from, to) =
+        match m {
+            None => return 0,
+            Some(Basic(f, t))        => (1, 0, *f, *t),
+            Some(En_passant(f, t))   => (2, 0, *f, *t),
+            Some(Castle_king)        => (3, 0, 0, 0),
+            Some(Castle_queen)       => (4, 0, 0, 0),
+            Some(Promotion(p, f, t)) => (5, *p as u64, *f, *t),
+        };
+
+    kind << 15 | piece << 12 | (from as u64) << 6 | to as u64
+}
+
+fn unpack_move(n: u64) -> Option<Move> {
+    let (from, to) = ((n >> 6 & 63) as u8, (n & 63) as u8);
+    let piece = [Piece::Empty, Piece::Pawn, Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen, Piece::King];
+
+    match n >> 15 {
+        1 => Some(Basic(from, to)),
+        2 => Some(En_passant(from, to)),
+        3 => Some(Castle_king),
+        4 => Some(Castle_queen),
+        5 => Some(Promotion(piece[(n >> 12 & 7) as usize % 7], from, to)),
+        _ => None,
+    }
+}
+
+// Each slot is the packed entry data and the key xored with it, so that a
+// slot torn by two threads writing at once fails the key check instead of
+// returning a mix of both entries. No locks are needed to share the table.
+pub struct TTable {
+    entries: Vec<[AtomicU64; 2]>,
+}
+
+impl TTable {
+    pub fn new(mb: usize) -> TTable {
+        let len = (mb.max(1) << 20) / std::mem::size_of::<[AtomicU64; 2]>();
+
+        TTable {
+            entries: (0..len).map(|_| Default::default()).collect(),
+        }
+    }
+
+    pub fn clear(&self) {
+        for e in self.entries.iter() {
+            e[0].store(0, Ordering::Relaxed);
+            e[1].store(0, Ordering::Relaxed);
+        }
+    }
+
+    fn index(&self, key: u64) -> usize {
+        (key % self.entries.len() as u64) as usize
+    }
+
+    pub fn probe(&self, key: u64) -> Option<Entry> {
+        let slot = &self.entries[self.index(key)];
+        let data = slot[1].load(Ordering::Relaxed);
+
+        if data == 0 || slot[0].load(Ordering::Relaxed) ^ data != key {
+            return None;
+        }
+
+        Some(Entry {
+            depth: (data >> 32) as u8 - 1,
+            bound: [Bound::Exact, Bound::Lower, Bound::Upper][(data >> 40 & 3) as usize % 3],
+            score: data as u32 as i32,
+            mov: unpack_move(data >> 42),
+        })
+    }
+
+    pub fn store(&self,
+                 key: u64,
+                 depth: usize,
+                 bound: Bound,
+                 score: i32,
+                 mov: Option<Move>,
+                 ply: usize)
+    {
+        if let Some(e) = self.probe(key) {
+            if e.depth as usize > depth && bound != Bound::Exact {
+                return;
+            }
+        }
+
+        // the depth is stored plus one so that an entry is never all zero
+        let data =
+            score_to_tt(score, ply) as u32 as u64 |
+            (depth.min(254) as u64 + 1) << 32 |
+            (bound as u64) << 40 |
+            pack_move(&mov) << 42;
+
+        let slot = &self.entries[self.index(key)];
+        slot[0].store(key ^ data, Ordering::Relaxed);
+        slot[1].store(data, Ordering::Relaxed);
+    }
+}
+pub fn score_to_tt(score: i32, ply: usize) -> i32 {
+    if score >= MATE_BOUND {
+        score + ply as i32
+    } else if score <= -MATE_BOUND {
+        score - ply as i32
+    } else {
+        score
+    }
+}
+
+pub fn score_from_tt(score: i32, ply: usize) -> i32 {
+    if score >= MATE_BOUND {
+        score - ply as i32
+    } else if score <= -MATE_BOUND {
+        score + ply as i32
+    } else {
+        score
+    }
+}
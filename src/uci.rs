@@ -0,0 +1,217 @@
+use crate::book::Book;
+use crate::gen_table::{Tables, new_tables};
+use crate::movegen::{Moves, Move};
+use crate::position::{Position, START_FEN};
+use crate::search::{Search, SearchLimits};
+use crate::syzygy::{Probe, Syzygy};
+use crate::tt::TTable;
+
+use std::io::BufRead;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+
+pub fn parse_move(pos: &Position, s: &str) -> Option<Move> {
+    let mut pos = pos.clone();
+    pos.set_moves();
+
+    pos.gen_moves()
+        .into_iter()
+        .find(|m| Moves::move_to_string(m, pos.board.inverted) == s)
+}
+
+// parses the arguments of a "position" command
+pub fn parse_position<'a>(tables: &'a Tables, words: &[&str]) -> Option<Position<'a>> {
+    let moves_start = words.iter().position(|w| *w == "moves").unwrap_or(words.len());
+
+    let mut pos =
+        match words.first() {
+            Some(&"startpos") => Position::from_fen(tables, START_FEN),
+            Some(&"fen") => Position::try_from_fen(tables, &words[1..moves_start].join(" ")).ok()?,
+            _ => return None,
+        };
+
+    for w in words.iter().skip(moves_start + 1) {
+        let mov = parse_move(&pos, w)?;
+        pos.do_move(&mov);
+        pos.board.invert();
+    }
+
+    pos.set_moves();
+    Some(pos)
+}
+
+fn parse_go(words: &[&str], inverted: bool) -> SearchLimits {
+    let mut limits = SearchLimits::default();
+    let mut clock = None;
+    let mut inc = 0;
+    let mut moves_to_go = None;
+    let mut i = 0;
+
+    while i < words.len() {
+        let arg = words.get(i + 1).and_then(|w| w.parse::<u128>().ok());
+
+        match (words[i], arg) {
+            ("wtime", Some(n)) if !inverted => clock = Some(n),
+            ("btime", Some(n)) if  inverted => clock = Some(n),
+            ("winc" , Some(n)) if !inverted => inc = n,
+            ("binc" , Some(n)) if  inverted => inc = n,
+            ("movestogo", Some(n)) => moves_to_go = Some(n),
+            ("movetime", Some(n)) => limits.time = Some(n),
+            ("depth", Some(n)) => limits.depth = Some(n as usize),
+            ("nodes", Some(n)) => limits.nodes = Some(n as usize),
+            ("infinite", _) => {
+                i += 1;
+                continue;
+            }
+            _ => {}
+        }
+        i += if arg.is_some() {2} else {1};
+    }
+
+    if limits.time.is_none() {
+        if let Some(clock) = clock {
+            limits.set_clock(clock, inc, moves_to_go);
+        }
+    }
+
+    limits
+}
+
+fn stop_search(stop: &Arc<AtomicBool>,
+               handle: &mut Option<JoinHandle<Arc<TTable>>>,
+               tt: &mut Option<Arc<TTable>>)
+{
+    stop.store(true, Ordering::Relaxed);
+    if let Some(h) = handle.take() {
+        *tt = Some(h.join().unwrap());
+    }
+}
+
+pub fn run() {
+    let tables: &'static Tables = Box::leak(Box::new(new_tables()));
+    let mut pos = Position::from_fen(tables, START_FEN);
+    let mut stop = Arc::new(AtomicBool::new(false));
+    let mut handle: Option<JoinHandle<Arc<TTable>>> = None;
+    let mut tt = Some(Arc::new(TTable::new(16)));
+    let mut threads = 1;
+    let mut multipv = 1;
+    let mut book: Option<Book> = None;
+    let mut book_file = String::new();
+    let (mut book_depth, mut book_best) = (20, false);
+    let mut tb: Option<Arc<dyn Probe>> = None;
+
+    let stdin = std::io::stdin();
+
+    for line in stdin.lock().lines() {
+        let line = match line {
+            Ok(l) => l,
+            Err(_) => break,
+        };
+        let words: Vec<&str> = line.split_whitespace().collect();
+
+        match words.first() {
+            Some(&"uci") => {
+                println!("id name chess_engine");
+                println!("id author Nathan Koppel");
+                println!("option name Hash type spin default 16 min 1 max 4096");
+                println!("option name Threads type spin default 1 min 1 max 256");
+                println!("option name MultiPV type spin default 1 min 1 max 256");
+                println!("option name BookFile type string default <empty>");
+                println!("option name BookDepth type spin default 20 min 0 max 1000");
+                println!("option name BookBest type check default false");
+                println!("option name SyzygyPath type string default <empty>");
+                println!("uciok");
+            },
+            Some(&"isready") => println!("readyok"),
+            Some(&"setoption") => {
+                stop_search(&stop, &mut handle, &mut tt);
+                let value = words.iter().position(|w| *w == "value").map_or(String::new(), |i| words[i + 1..].join(" "));
+
+                match words.get(2) {
+                    Some(&"Hash") => {
+                        if let Ok(mb) = value.parse() {
+                            tt = Some(Arc::new(TTable::new(mb)));
+                        }
+                    },
+                    Some(&"Threads") => threads = value.parse().unwrap_or(threads).max(1),
+                    Some(&"MultiPV") => multipv = value.parse().unwrap_or(multipv).max(1),
+                    Some(&"BookFile") => book_file = value,
+                    Some(&"BookDepth") => book_depth = value.parse().unwrap_or(book_depth),
+                    Some(&"BookBest") => book_best = value == "true",
+                    Some(&"SyzygyPath") => {
+                        tb = None;
+                        if !value.is_empty() && value != "<empty>" {
+                            match Syzygy::open(&value) {
+                                Ok(t) => {
+                                    println!("info string found {} tablebase files", t.len());
+                                    tb = Some(Arc::new(t));
+                                },
+                                Err(e) => println!("info string cannot open {}: {}", value, e),
+                            }
+                        }
+                    },
+                    _ => {}
+                }
+
+                if !words.get(2).map_or(false, |w| w.starts_with("Book")) {
+                    continue;
+                }
+
+                book = None;
+                if !book_file.is_empty() && book_file != "<empty>" {
+                    match Book::open(&book_file) {
+                        Ok(b) => book = Some(b),
+                        Err(e) => println!("info string cannot open book: {}", e),
+                    }
+                }
+                if let Some(b) = book.as_mut() {
+                    b.depth = book_depth;
+                    b.best = book_best;
+                }
+            },
+            Some(&"ucinewgame") => {
+                stop_search(&stop, &mut handle, &mut tt);
+                pos = Position::from_fen(tables, START_FEN);
+                tt.as_ref().unwrap().clear();
+            },
+            Some(&"position") => {
+                stop_search(&stop, &mut handle, &mut tt);
+                match parse_position(tables, &words[1..]) {
+                    Some(p) => pos = p,
+                    None => println!("info string invalid position"),
+                }
+            },
+            Some(&"go") => {
+                stop_search(&stop, &mut handle, &mut tt);
+
+                if let Some(m) = book.as_ref().and_then(|b| b.choose(&pos)) {
+                    println!("bestmove {}", Moves::move_to_string(&m, pos.board.inverted));
+                    continue;
+                }
+
+                let limits = parse_go(&words[1..], pos.board.inverted);
+                let mut search = Search::with_tt(limits, tt.take().unwrap());
+                search.tb = tb.clone();
+                search.threads = threads;
+                search.on_info = Some(Box::new(|info| println!("info {}", info)));
+                stop = search.stop.clone();
+                let pos = pos.clone();
+
+                handle = Some(std::thread::spawn(move || {
+                    let inverted = pos.board.inverted;
+                    match search.top_moves(pos, multipv).first() {
+                        Some((m, _, _)) => println!("bestmove {}", Moves::move_to_string(m, inverted)),
+                        None => println!("bestmove 0000"),
+                    }
+                    search.tt
+                }));
+            },
+            Some(&"stop") => stop_search(&stop, &mut handle, &mut tt),
+            Some(&"quit") => break,
+            _ => {}
+        }
+    }
+
+    stop_search(&stop, &mut handle, &mut tt);
+}
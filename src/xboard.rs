@@ -0,0 +1,380 @@
+use crate::book::Book;
+use crate::gen_table::{Tables, new_tables};
+use crate::movegen::{Moves, Move};
+use crate::position::{Position, START_FEN};
+use crate::search::{Search, SearchLimits, mate_in};
+use crate::syzygy::{Probe, Syzygy};
+use crate::tt::TTable;
+use crate::uci::parse_move;
+
+use std::io::BufRead;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Sender};
+
+enum Event {
+    Input(String),
+    Done(usize, Option<Move>),
+}
+
+struct Clock {
+    moves_per_control: usize,
+    base: u128,
+    inc: u128,
+    fixed: Option<u128>,
+    depth: Option<usize>,
+    remaining: Option<u128>,
+}
+
+impl Clock {
+    fn new() -> Clock {
+        Clock {
+            moves_per_control: 0,
+            base: 0,
+            inc: 0,
+            fixed: None,
+            depth: None,
+            remaining: None,
+        }
+    }
+
+    fn limits(&self, full_moves: usize) -> SearchLimits {
+        let mut limits = SearchLimits::default();
+        limits.depth = self.depth;
+
+        if let Some(t) = self.fixed {
+            limits.time = Some(t);
+        } else if let Some(clock) = self.remaining.or(Some(self.base)).filter(|c| *c > 0) {
+            let moves_to_go =
+                if self.moves_per_control > 0 {
+                    let n = self.moves_per_control;
+                    Some((n - (full_moves.max(1) - 1) % n) as u128)
+                } else {
+                    None
+                };
+            limits.set_clock(clock, self.inc, moves_to_go);
+        }
+
+        limits
+    }
+}
+
+// parses a time given as "minutes" or "minutes:seconds" into milliseconds
+fn parse_base(s: &str) -> Option<u128> {
+    let mut parts = s.split(':');
+    let min: u128 = parts.next()?.parse().ok()?;
+    let sec: u128 = parts.next().map_or(Some(0), |s| s.parse().ok())?;
+    Some((min * 60 + sec) * 1000)
+}
+
+fn result_string(pos: &mut Position) -> Option<&'static str> {
+    pos.set_moves();
+    match pos.test_endgame() {
+        Some(0) => Some("0-1 {Black mates}"),
+        Some(1) => Some("1/2-1/2 {Draw}"),
+        Some(2) => Some("1-0 {White mates}"),
+        _ => None,
+    }
+}
+
+struct Game<'a> {
+    tables: &'a Tables,
+    history: Vec<Position<'a>>,
+    force: bool,
+    post: bool,
+    engine_black: bool,
+    clock: Clock,
+    search_id: usize,
+    searching: bool,
+    stop: Arc<AtomicBool>,
+    tt: Arc<TTable>,
+    hash_mb: usize,
+    cores: usize,
+    book: Option<Book>,
+    book_file: String,
+    book_depth: usize,
+    book_best: bool,
+    tb: Option<Arc<dyn Probe>>,
+}
+
+impl Game<'static> {
+    fn pos(&self) -> &Position<'static> {
+        self.history.last().unwrap()
+    }
+
+    fn reset(&mut self, pos: Position<'static>) {
+        self.cancel();
+        self.history = vec![pos];
+    }
+
+    fn cancel(&mut self) {
+        self.search_id += 1;
+        self.searching = false;
+        self.stop.store(true, Ordering::Relaxed);
+    }
+
+    fn play(&mut self, m: &Move) {
+        let mut pos = self.pos().clone();
+        pos.do_move(m);
+        pos.board.invert();
+        pos.set_moves();
+        self.history.push(pos);
+    }
+
+    fn start_search(&mut self, tx: &Sender<Event>) {
+        let mut pos = self.pos().clone();
+
+        if let Some(result) = result_string(&mut pos) {
+            println!("{}", result);
+            return;
+        }
+
+        self.cancel();
+        self.engine_black = pos.board.inverted;
+
+        if let Some(m) = self.book.as_ref().and_then(|b| b.choose(&pos)) {
+            println!("move {}", Moves::move_to_string(&m, pos.board.inverted));
+            self.play(&m);
+            if let Some(result) = result_string(&mut self.pos().clone()) {
+                println!("{}", result);
+            }
+            return;
+        }
+
+        self.searching = true;
+
+        // a cancelled search still running shares the table with this one
+        let mut search = Search::with_tt(self.clock.limits(pos.full_moves), self.tt.clone());
+        search.tb = self.tb.clone();
+        search.threads = self.cores;
+        if self.post {
+            search.on_info = Some(Box::new(|info| {
+                // mate in n is written as 100000 + n
+                let score =
+                    match mate_in(info.score) {
+                        Some(n) if n > 0 => 100000 + n,
+                        Some(n) => -100000 + n,
+                        None => info.score,
+                    };
+                println!("{} {} {} {} {}", info.depth, score, info.time / 10, info.nodes, info.pv.join(" "));
+            }));
+        }
+        self.stop = search.stop.clone();
+
+        let id = self.search_id;
+        let tx = tx.clone();
+
+        std::thread::spawn(move || {
+            let (m, _) = search.run(pos);
+            let _ = tx.send(Event::Done(id, m));
+        });
+    }
+
+    fn finish_search(&mut self, m: Option<Move>) {
+        self.searching = false;
+        let inverted = self.pos().board.inverted;
+
+        let m =
+            match m {
+                Some(m) => m,
+                None => return,
+            };
+
+        println!("move {}", Moves::move_to_string(&m, inverted));
+        self.play(&m);
+
+        let mut pos = self.pos().clone();
+        if let Some(result) = result_string(&mut pos) {
+            println!("{}", result);
+        }
+    }
+
+    // handles "option NAME=VALUE" for the options announced in protover
+    fn set_option(&mut self, name: &str, value: &str) {
+        match name {
+            "Book File" => self.book_file = value.to_string(),
+            "Book Depth" => self.book_depth = value.parse().unwrap_or(self.book_depth),
+            "Best Book Move" => self.book_best = value == "1",
+            _ => return,
+        }
+
+        self.book = None;
+        if !self.book_file.is_empty() {
+            match Book::open(&self.book_file) {
+                Ok(b) => self.book = Some(b),
+                Err(e) => println!("tellusererror Cannot open book: {}", e),
+            }
+        }
+        if let Some(b) = self.book.as_mut() {
+            b.depth = self.book_depth;
+            b.best = self.book_best;
+        }
+    }
+
+    fn engine_to_move(&self) -> bool {
+        !self.force && !self.searching && self.pos().board.inverted == self.engine_black
+    }
+}
+
+pub fn run() {
+    let tables: &'static Tables = Box::leak(Box::new(new_tables()));
+    let (tx, rx) = channel();
+
+    let input = tx.clone();
+    std::thread::spawn(move || {
+        let stdin = std::io::stdin();
+        for line in stdin.lock().lines() {
+            match line {
+                Ok(l) => if input.send(Event::Input(l)).is_err() {break},
+                Err(_) => break,
+            }
+        }
+        let _ = input.send(Event::Input("quit".to_string()));
+    });
+
+    let mut game = Game {
+        tables,
+        history: vec![Position::from_fen(tables, START_FEN)],
+        force: false,
+        post: false,
+        engine_black: true,
+        clock: Clock::new(),
+        search_id: 0,
+        searching: false,
+        stop: Arc::new(AtomicBool::new(false)),
+        tt: Arc::new(TTable::new(16)),
+        hash_mb: 16,
+        cores: 1,
+        book: None,
+        book_file: String::new(),
+        book_depth: 20,
+        book_best: false,
+        tb: None,
+    };
+
+    for event in rx.iter() {
+        let line =
+            match event {
+                Event::Done(id, m) => {
+                    if id == game.search_id {
+                        game.finish_search(m);
+                    }
+                    continue;
+                },
+                Event::Input(l) => l,
+            };
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let arg = |i: usize| words.get(i).and_then(|w| w.parse::<u128>().ok());
+
+        match words.first() {
+            Some(&"protover") => {
+                println!("feature myname=\"chess_engine\" setboard=1 usermove=1 \
+                          memory=1 smp=1 ping=1 sigint=0 sigterm=0 colors=0 egt=\"syzygy\" \
+                          option=\"Book File -file \" option=\"Book Depth -spin 20 0 1000\" \
+                          option=\"Best Book Move -check 0\" done=1");
+            },
+            Some(&"new") => {
+                game.reset(Position::from_fen(game.tables, START_FEN));
+                game.force = false;
+                game.engine_black = true;
+                game.clock.depth = None;
+                game.clock.fixed = None;
+                game.tt.clear();
+            },
+            Some(&"memory") => {
+                if let Some(mb) = arg(1) {
+                    game.cancel();
+                    game.hash_mb = mb as usize;
+                    game.tt = Arc::new(TTable::new(game.hash_mb));
+                }
+            },
+            Some(&"cores") => {
+                if let Some(n) = arg(1) {
+                    game.cancel();
+                    game.cores = (n as usize).max(1);
+                }
+            },
+            Some(&"setboard") => {
+                match Position::try_from_fen(game.tables, &words[1..].join(" ")) {
+                    Ok(pos) => game.reset(pos),
+                    Err(e) => println!("tellusererror Illegal position: {}", e),
+                }
+            },
+            Some(&"usermove") => {
+                if game.searching {
+                    game.cancel();
+                }
+                match words.get(1).and_then(|w| parse_move(game.pos(), w)) {
+                    Some(m) => game.play(&m),
+                    None => {
+                        println!("Illegal move: {}", words.get(1).unwrap_or(&""));
+                        continue;
+                    }
+                }
+                if game.engine_to_move() {
+                    game.start_search(&tx);
+                }
+            },
+            Some(&"go") => {
+                game.force = false;
+                game.start_search(&tx);
+            },
+            Some(&"force") => {
+                game.cancel();
+                game.force = true;
+            },
+            Some(&"?") => game.stop.store(true, Ordering::Relaxed),
+            Some(&"level") => {
+                game.clock.moves_per_control = arg(1).unwrap_or(0) as usize;
+                game.clock.base = words.get(2).and_then(|w| parse_base(w)).unwrap_or(0);
+                game.clock.inc = words.get(3).and_then(|w| w.parse::<f64>().ok())
+                    .map_or(0, |i| (i * 1000.) as u128);
+                game.clock.fixed = None;
+            },
+            Some(&"st") => game.clock.fixed = arg(1).map(|s| s * 1000),
+            Some(&"sd") => game.clock.depth = arg(1).map(|d| d as usize),
+            Some(&"time") => game.clock.remaining = arg(1).map(|cs| cs * 10),
+            Some(&"otim") => {},
+            Some(&"undo") => {
+                game.cancel();
+                if game.history.len() > 1 {
+                    game.history.pop();
+                }
+            },
+            Some(&"remove") => {
+                game.cancel();
+                for _ in 0..2 {
+                    if game.history.len() > 1 {
+                        game.history.pop();
+                    }
+                }
+            },
+            Some(&"result") => {
+                game.cancel();
+                game.force = true;
+            },
+            Some(&"option") => {
+                if let Some((name, value)) = line.trim_start()["option".len()..].trim().split_once('=') {
+                    game.set_option(name, value);
+                }
+            },
+            Some(&"egtpath") => {
+                if let (Some(&"syzygy"), Some(_)) = (words.get(1), words.get(2)) {
+                    let dir = words[2..].join(" ");
+                    match Syzygy::open(&dir) {
+                        Ok(t) => game.tb = Some(Arc::new(t)),
+                        Err(e) => println!("tellusererror Cannot open {}: {}", dir, e),
+                    }
+                }
+            },
+            Some(&"ping") => println!("pong {}", words.get(1).unwrap_or(&"")),
+            Some(&"post") => game.post = true,
+            Some(&"nopost") => game.post = false,
+            Some(&"quit") => break,
+            _ => {}
+        }
+    }
+
+    game.cancel();
+}
@@ -0,0 +1,91 @@
+use crate::board::{Board, Piece, Piece::*};
+use crate::gen_table::LocStack;
+
+const PIECES: usize = 0;
+const SIDE: usize = 12 * 64;
+const CASTLE: usize = SIDE + 1;
+const EP: usize = CASTLE + 4;
+
+const KEYS: [u64; EP + 8] = gen_keys();
+
+// splitmix64, so the keys are the same on every run and every build
+const fn gen_keys() -> [u64; EP + 8] {
+    let mut out = [0; EP + 8];
+    let mut state: u64 = 0x9e3779b97f4a7c15;
+    let mut i = 0;
+
+    while i < out.len() {
+        state = state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        out[i] = z ^ (z >> 31);
+        i += 1;
+    }
+
+    out
+}
+
+// key of a piece on a square, given as a location and color relative to the
+// player to move
+pub fn piece_key(piece: Piece, is_other: bool, loc: u8, inverted: bool) -> u64 {
+    let black = is_other ^ inverted;
+    let sq = if inverted {loc ^ 56} else {loc};
+
+    match piece {
+        Empty => 0,
+        _ => KEYS[PIECES + (black as usize * 6 + piece as usize - 1) * 64 + sq as usize]
+    }
+}
+
+pub fn side_key() -> u64 {
+    KEYS[SIDE]
+}
+
+pub fn castle_key(board: &Board) -> u64 {
+    let (white, black) =
+        if board.inverted {
+            (board.castle_other, board.castle_curr)
+        } else {
+            (board.castle_curr, board.castle_other)
+        };
+
+    let mut out = 0;
+    for (i, c) in white.iter().chain(black.iter()).enumerate() {
+        if *c {
+            out ^= KEYS[CASTLE + i];
+        }
+    }
+    out
+}
+
+// en-passant files are stored in rank 8 of the pawn bitboard
+pub fn ep_key(pawn: u64) -> u64 {
+    let mut out = 0;
+    for file in LocStack(pawn >> 56) {
+        out ^= KEYS[EP + file];
+    }
+    out
+}
+
+impl Board {
+    pub fn gen_key(&self) -> u64 {
+        let mut out = 0;
+
+        for loc in LocStack(self.all()) {
+            let loc = loc as u8;
+            let (piece, is_other) = self.get_loc(loc);
+            out ^= piece_key(piece, is_other, loc, self.inverted);
+        }
+
+        if self.inverted {
+            out ^= side_key();
+        }
+
+        out ^ castle_key(self) ^ ep_key(self.pawn)
+    }
+
+    pub fn piece_key(&self, piece: Piece, is_other: bool, loc: u8) -> u64 {
+        piece_key(piece, is_other, loc, self.inverted)
+    }
+}